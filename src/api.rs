@@ -0,0 +1,738 @@
+/* 
+   Copyright (c) 2022 ArSysOp.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+  
+       http:  www.apache.org/licenses/LICENSE-2.0
+  
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+  
+   SPDX-License-Identifier: Apache-2.0
+  
+   Contributors:
+     ArSysOp - initial API and implementation
+*/
+
+use clang::{Accessibility, Entity, EntityKind, Linkage, Type, Visibility};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, Result, Write};
+use std::path::{Path, PathBuf};
+use crate::diff::match_declarations;
+use crate::error::Error;
+use crate::graph::{visit_ast, should_be_excluded, Exclusion, Array, Key, WriteDocument};
+use crate::json::{Bracket, DocumentSerializer};
+use crate::kind::{get_accessibility_label, get_kind_label, FUNCTION_KINDS, RECORD_KINDS};
+use crate::output::{normalize_path, API_FILE_NAME};
+use crate::get_name::*;
+
+const TYPEDEF_KINDS: [EntityKind; 2] = [EntityKind::TypedefDecl, EntityKind::TypeAliasDecl];
+
+fn get_canonical_name(type_: Type) -> String {
+    type_.get_canonical_type().get_display_name()
+}
+
+// the entity and all its enclosing declarations are visible to users of the library: 
+// neither private, hidden, internal nor in an anonymous namespace or a function
+fn is_exported(entity: Entity) -> bool {
+    let mut current = entity;
+    loop {
+        let internal = matches!(current.get_linkage(), Some(Linkage::Internal | Linkage::UniqueExternal | Linkage::Automatic));
+        if internal || current.get_visibility() == Some(Visibility::Hidden) 
+            || current.get_accessibility() == Some(Accessibility::Private) {
+            return false;
+        }
+        let Some(parent) = current.get_semantic_parent() else {
+            return true;
+        };
+        match parent.get_kind() {
+            EntityKind::TranslationUnit => return true,
+            EntityKind::Namespace if parent.get_name().is_none() => return false,
+            kind if kind == EntityKind::Namespace || RECORD_KINDS.contains(&kind) => current = parent,
+            _ => return false,
+        }
+    }
+}
+
+/// Field of a record, in the layout of its record.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+pub struct Field {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_name: String,
+    /// In bits from the start of the record, unknown in templates.
+    pub offset: Option<u64>,
+    pub access: String,
+}
+
+impl WriteDocument for Field {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line(Key::Name.get_key(), self.name.as_bytes())?;
+        json.render_line(Key::Type.get_key(), self.type_name.as_bytes())?;
+        if let Some(offset) = self.offset {
+            json.render_number_line(Key::Offset.get_key(), offset)?;
+        }
+        json.render_line(Key::Access.get_key(), self.access.as_bytes())?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+/// Enum constant with its value, unless libclang could not evaluate it. 
+/// The value covers the range of both signed and unsigned 64-bit underlying types.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+pub struct Constant {
+    pub name: String,
+    #[serde(default)]
+    pub value: Option<i128>,
+}
+
+impl Constant {
+    fn new(entity: Entity, unsigned: bool) -> Constant {
+        Constant {
+            name: return_empty_if_null(entity.get_name()),
+            value: entity.get_enum_constant_value().map(|(signed, unsigned_value)| match unsigned {
+                true => i128::from(unsigned_value),
+                false => i128::from(signed),
+            }),
+        }
+    }
+
+    fn get_value(&self) -> String {
+        self.value.map(|value| value.to_string()).unwrap_or_else(|| String::from("unknown"))
+    }
+}
+
+impl WriteDocument for Constant {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line(Key::Name.get_key(), self.name.as_bytes())?;
+        if let Some(value) = self.value {
+            match i64::try_from(value) {
+                Ok(value) => json.render_signed_number_line(Key::Value.get_key(), value)?,
+                // beyond i64 only with an unsigned underlying type
+                Err(_) => json.render_number_line(Key::Value.get_key(), value as u64)?,
+            }
+        }
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+/// One exported declaration. Types are canonical, so renaming a typedef used in a signature is not a change. 
+/// Only the attributes of its kind are set: signatures for functions, layouts for records, 
+/// constants for enums, and the underlying type for enums and typedefs.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ApiDeclaration {
+    pub usr: String,
+    pub kind: String,
+    /// Qualified with the enclosing namespaces and classes.
+    pub name: String,
+    pub location: String,
+    pub access: String,
+    pub signature: String,
+    pub result: Option<String>,
+    pub parameters: Vec<String>,
+    pub variadic: bool,
+    #[serde(rename = "const")]
+    pub is_const: bool,
+    #[serde(rename = "static")]
+    pub is_static: bool,
+    #[serde(rename = "virtual")]
+    pub is_virtual: bool,
+    #[serde(rename = "pure")]
+    pub is_pure: bool,
+    /// In bytes, unknown in templates.
+    pub size: Option<u64>,
+    pub alignment: Option<u64>,
+    pub bases: Vec<String>,
+    /// All fields, private ones too, in declaration order.
+    pub fields: Vec<Field>,
+    /// USRs of the virtual methods declared in the record, in declaration order.
+    pub virtual_methods: Vec<String>,
+    pub underlying: Option<String>,
+    pub constants: Vec<Constant>,
+}
+
+impl WriteDocument for ApiDeclaration {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        for (key, value) in [(Key::Usr, &self.usr), (Key::Kind, &self.kind), (Key::Name, &self.name), 
+            (Key::Location, &self.location), (Key::Access, &self.access)] {
+            json.render_line(key.get_key(), value.as_bytes())?;
+        }
+        if !self.signature.is_empty() {
+            json.render_line(Key::Signature.get_key(), self.signature.as_bytes())?;
+            if let Some(result) = &self.result {
+                json.render_line(Key::Result.get_key(), result.as_bytes())?;
+            }
+            Array::new(Key::Parameters, self.parameters.clone()).serialize(json)?;
+            for (key, value) in [(Key::Variadic, self.variadic), (Key::Const, self.is_const), (Key::Static, self.is_static), 
+                (Key::Virtual, self.is_virtual), (Key::Pure, self.is_pure)] {
+                json.render_bool_line(key.get_key(), value)?;
+            }
+        }
+        for (key, value) in [(Key::Size, self.size), (Key::Alignment, self.alignment)] {
+            if let Some(value) = value {
+                json.render_number_line(key.get_key(), value)?;
+            }
+        }
+        if RECORD_KINDS.iter().any(|kind| get_kind_label(*kind) == self.kind) {
+            Array::new(Key::Bases, self.bases.clone()).serialize(json)?;
+            Array::new(Key::Fields, self.fields.clone()).serialize(json)?;
+            Array::new(Key::VirtualMethods, self.virtual_methods.clone()).serialize(json)?;
+        }
+        if let Some(underlying) = &self.underlying {
+            json.render_line(Key::Underlying.get_key(), underlying.as_bytes())?;
+        }
+        if get_kind_label(EntityKind::EnumDecl) == self.kind {
+            Array::new(Key::Constants, self.constants.clone()).serialize(json)?;
+        }
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+impl ApiDeclaration {
+    fn new(entity: Entity, usr: String) -> ApiDeclaration {
+        let kind = entity.get_kind();
+        let mut declaration = ApiDeclaration {
+            usr,
+            kind: get_kind_label(kind),
+            name: get_qualified_name(entity),
+            location: entity.get_location().get_name(),
+            access: entity.get_accessibility().map(get_accessibility_label).unwrap_or_default(),
+            ..ApiDeclaration::default()
+        };
+        let children = entity.get_children();
+        if FUNCTION_KINDS.contains(&kind) {
+            declaration.result = entity.get_result_type().map(get_canonical_name);
+            declaration.parameters = children.iter()
+                .filter(|child| child.get_kind() == EntityKind::ParmDecl)
+                .map(|parameter| parameter.get_type().map(get_canonical_name).unwrap_or_default())
+                .collect();
+            declaration.variadic = entity.is_variadic();
+            declaration.is_const = entity.is_const_method();
+            declaration.is_static = entity.is_static_method();
+            declaration.is_virtual = entity.is_virtual_method();
+            declaration.is_pure = entity.is_pure_virtual_method();
+            let mut parameters = declaration.parameters.clone();
+            if declaration.variadic {
+                parameters.push(String::from("..."));
+            }
+            declaration.signature = format!("{}{}({}){}", 
+                declaration.result.as_ref().map(|result| result.clone() + " ").unwrap_or_default(), 
+                declaration.name, parameters.join(", "), if declaration.is_const { " const" } else { "" });
+        } else if RECORD_KINDS.contains(&kind) {
+            let type_ = entity.get_type();
+            declaration.size = type_.and_then(|type_| type_.get_sizeof().ok()).map(|size| size as u64);
+            declaration.alignment = type_.and_then(|type_| type_.get_alignof().ok()).map(|alignment| alignment as u64);
+            for child in children {
+                match child.get_kind() {
+                    EntityKind::BaseSpecifier => declaration.bases.push(format!("{}{} {}", 
+                        child.get_accessibility().map(get_accessibility_label).unwrap_or_default(),
+                        if child.is_virtual_base() { " virtual" } else { "" },
+                        child.get_type().map(get_canonical_name).unwrap_or_default())),
+                    EntityKind::FieldDecl => declaration.fields.push(Field {
+                        name: return_empty_if_null(child.get_name()),
+                        type_name: child.get_type().map(get_canonical_name).unwrap_or_default(),
+                        offset: child.get_offset_of_field().ok().map(|offset| offset as u64),
+                        access: child.get_accessibility().map(get_accessibility_label).unwrap_or_default(),
+                    }),
+                    EntityKind::Method | EntityKind::Destructor if child.is_virtual_method() => {
+                        declaration.virtual_methods.extend(get_usr(child));
+                    },
+                    _ => {},
+                }
+            }
+        } else if kind == EntityKind::EnumDecl {
+            let underlying = entity.get_enum_underlying_type().map(|type_| type_.get_canonical_type());
+            let unsigned = underlying.is_some_and(|type_| type_.is_unsigned_integer());
+            declaration.underlying = underlying.map(|type_| type_.get_display_name());
+            declaration.constants = children.iter()
+                .filter(|child| child.get_kind() == EntityKind::EnumConstantDecl)
+                .map(|constant| Constant::new(*constant, unsigned))
+                .collect();
+        } else {
+            declaration.underlying = entity.get_typedef_underlying_type().map(get_canonical_name);
+        }
+        declaration
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ApiFile {
+    declarations: Vec<ApiDeclaration>,
+}
+
+/// Exported functions, methods, classes, enums and typedefs of the public headers, ordered by USR.
+#[derive(Clone, Debug, Default)]
+pub struct ApiSurface {
+    declarations: BTreeMap<String, ApiDeclaration>,
+}
+
+impl WriteDocument for ApiSurface {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line_without_value(Key::Declarations.get_key())?;
+        json.render_bracket(Bracket::LBrace)?;
+        json.prefix.expand();
+        for declaration in self.declarations.values() {
+            declaration.serialize(json)?;
+        }
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RBrace)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+impl ApiSurface {
+    fn record(&mut self, entity: Entity, public_headers: &[PathBuf]) {
+        let kind = entity.get_kind();
+        let api_kind = FUNCTION_KINDS.contains(&kind) || TYPEDEF_KINDS.contains(&kind) 
+            // forward declarations have no layout
+            || ((RECORD_KINDS.contains(&kind) || kind == EntityKind::EnumDecl) && entity.is_definition());
+        if !api_kind {
+            return;
+        }
+        let Some(usr) = get_usr(entity) else {
+            return;
+        };
+        if self.declarations.contains_key(&usr) || !is_exported(entity) {
+            return;
+        }
+        if !public_headers.is_empty() {
+            let file = entity.get_location().and_then(|location| location.get_file_location().file);
+            let public = file.is_some_and(|file| {
+                let path = normalize_path(&file.get_name());
+                public_headers.iter().any(|prefix| path.starts_with(prefix))
+            });
+            if !public {
+                return;
+            }
+        }
+        self.declarations.insert(usr.clone(), ApiDeclaration::new(entity, usr));
+    }
+
+    /// Merges the declarations of another translation unit, a declaration from a shared header is kept once.
+    pub fn merge(&mut self, other: ApiSurface) {
+        for (usr, declaration) in other.declarations {
+            self.declarations.entry(usr).or_insert(declaration);
+        }
+    }
+
+    /// Reads an `api.json` file, or the one in the given output directory.
+    pub fn read(path: &str) -> std::result::Result<ApiSurface, Error> {
+        let path = match Path::new(path).is_dir() {
+            true => PathBuf::from(path).join(API_FILE_NAME).to_string_lossy().to_string(),
+            false => String::from(path),
+        };
+        let file = File::open(&path).map_err(|err| Error::Io(path.clone(), err))?;
+        let api: ApiFile = serde_json::from_reader(BufReader::new(file)).map_err(|err| Error::Dump(path, err))?;
+        Ok(ApiSurface {
+            declarations: api.declarations.into_iter().map(|declaration| (declaration.usr.clone(), declaration)).collect(),
+        })
+    }
+}
+
+/// Collects the exported declarations, only from files below `public_headers` unless it is empty.
+pub fn collect_api(parent: Entity, exclusion: &Exclusion, public_headers: &[PathBuf]) -> ApiSurface {
+    let mut api = ApiSurface::default();
+    let recording_task = |entity: Entity| api.record(entity, public_headers);
+    let filter = |entity: Entity| !should_be_excluded(entity, exclusion);
+    let _ = visit_ast(parent, (recording_task, HashSet::new()), &filter).0;
+    api
+}
+
+/// What changed in a declaration between two API snapshots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ApiChangeKind {
+    Removed,
+    Added,
+    /// The result or the parameter types of a function changed.
+    SignatureChanged,
+    /// A method became or stopped being `const` or `static`.
+    QualifiersChanged,
+    VirtualAdded,
+    VirtualRemoved,
+    /// A virtual method became pure, derived classes have to override it.
+    PureAdded,
+    /// A member became protected or private.
+    AccessNarrowed,
+    SizeChanged,
+    AlignmentChanged,
+    BasesChanged,
+    FieldAdded,
+    FieldRemoved,
+    FieldTypeChanged,
+    /// A field is at another offset, because fields were reordered or inserted before it.
+    FieldMoved,
+    /// Virtual methods of a record were added, removed or reordered, so is its vtable.
+    VtableChanged,
+    /// The underlying type of an enum or a typedef changed.
+    UnderlyingChanged,
+    ConstantAdded,
+    ConstantRemoved,
+    ConstantChanged,
+}
+
+impl ApiChangeKind {
+    /// Name of the change in the JSON report, e.g. `signatureChanged`.
+    pub fn get_label(&self) -> &'static str {
+        match self {
+            ApiChangeKind::Removed => "removed",
+            ApiChangeKind::Added => "added",
+            ApiChangeKind::SignatureChanged => "signatureChanged",
+            ApiChangeKind::QualifiersChanged => "qualifiersChanged",
+            ApiChangeKind::VirtualAdded => "virtualAdded",
+            ApiChangeKind::VirtualRemoved => "virtualRemoved",
+            ApiChangeKind::PureAdded => "pureAdded",
+            ApiChangeKind::AccessNarrowed => "accessNarrowed",
+            ApiChangeKind::SizeChanged => "sizeChanged",
+            ApiChangeKind::AlignmentChanged => "alignmentChanged",
+            ApiChangeKind::BasesChanged => "basesChanged",
+            ApiChangeKind::FieldAdded => "fieldAdded",
+            ApiChangeKind::FieldRemoved => "fieldRemoved",
+            ApiChangeKind::FieldTypeChanged => "fieldTypeChanged",
+            ApiChangeKind::FieldMoved => "fieldMoved",
+            ApiChangeKind::VtableChanged => "vtableChanged",
+            ApiChangeKind::UnderlyingChanged => "underlyingChanged",
+            ApiChangeKind::ConstantAdded => "constantAdded",
+            ApiChangeKind::ConstantRemoved => "constantRemoved",
+            ApiChangeKind::ConstantChanged => "constantChanged",
+        }
+    }
+
+    // whether code using the declaration may stop compiling, and whether binaries built against it may stop working
+    fn get_breakage(&self) -> (bool, bool) {
+        match self {
+            ApiChangeKind::Added | ApiChangeKind::FieldAdded | ApiChangeKind::ConstantAdded => (false, false),
+            ApiChangeKind::PureAdded | ApiChangeKind::AccessNarrowed => (true, false),
+            ApiChangeKind::VirtualAdded | ApiChangeKind::SizeChanged | ApiChangeKind::AlignmentChanged 
+                | ApiChangeKind::FieldMoved | ApiChangeKind::VtableChanged | ApiChangeKind::ConstantChanged => (false, true),
+            ApiChangeKind::Removed | ApiChangeKind::SignatureChanged | ApiChangeKind::QualifiersChanged 
+                | ApiChangeKind::VirtualRemoved | ApiChangeKind::BasesChanged | ApiChangeKind::FieldRemoved 
+                | ApiChangeKind::FieldTypeChanged | ApiChangeKind::UnderlyingChanged | ApiChangeKind::ConstantRemoved => (true, true),
+        }
+    }
+}
+
+/// One change of the API surface. Changes of fields and enum constants name the member after its parent.
+#[derive(Clone, Debug)]
+pub struct ApiChange {
+    pub change: ApiChangeKind,
+    pub kind: String,
+    pub name: String,
+    /// The USR in the new snapshot, unless the declaration was removed.
+    pub usr: String,
+    pub location: String,
+    /// Signature, layout or value before and after the change.
+    pub values: Option<(String, String)>,
+    pub source_breaking: bool,
+    pub abi_breaking: bool,
+}
+
+impl WriteDocument for ApiChange {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line(Key::Change.get_key(), self.change.get_label().as_bytes())?;
+        for (key, value) in [(Key::Kind, &self.kind), (Key::Name, &self.name), (Key::Usr, &self.usr), (Key::Location, &self.location)] {
+            json.render_line(key.get_key(), value.as_bytes())?;
+        }
+        if let Some((old, new)) = &self.values {
+            json.render_line(Key::Old.get_key(), old.as_bytes())?;
+            json.render_line(Key::New.get_key(), new.as_bytes())?;
+        }
+        json.render_bool_line(Key::SourceBreaking.get_key(), self.source_breaking)?;
+        json.render_bool_line(Key::AbiBreaking.get_key(), self.abi_breaking)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+impl ApiChange {
+    fn new(change: ApiChangeKind, declaration: &ApiDeclaration, values: Option<(String, String)>) -> ApiChange {
+        let (source_breaking, abi_breaking) = change.get_breakage();
+        ApiChange {
+            change,
+            kind: declaration.kind.clone(),
+            name: declaration.name.clone(),
+            usr: declaration.usr.clone(),
+            location: declaration.location.clone(),
+            values,
+            source_breaking,
+            abi_breaking,
+        }
+    }
+
+    fn member(change: ApiChangeKind, parent: &ApiDeclaration, kind: EntityKind, name: &str, values: Option<(String, String)>) -> ApiChange {
+        ApiChange {
+            kind: get_kind_label(kind),
+            name: format!("{}::{}", parent.name, name),
+            ..ApiChange::new(change, parent, values)
+        }
+    }
+}
+
+fn get_access_rank(access: &str) -> u8 {
+    match access {
+        "private" => 2,
+        "protected" => 1,
+        _ => 0,
+    }
+}
+
+fn get_qualifiers(declaration: &ApiDeclaration) -> String {
+    let qualifiers = [(declaration.is_static, "static"), (declaration.is_const, "const")].iter()
+    .filter(|(set, _)| *set)
+    .map(|(_, qualifier)| *qualifier)
+    .collect::<Vec<&str>>()
+    .join(" ");
+    match qualifiers.is_empty() {
+        true => String::from("none"),
+        false => qualifiers,
+    }
+}
+
+fn compare_fields(old: &ApiDeclaration, new: &ApiDeclaration, changes: &mut Vec<ApiChange>) {
+    let new_fields = new.fields.iter().map(|field| (&field.name, field)).collect::<HashMap<_, _>>();
+    let mut field_change = |change, field: &Field, values| {
+        let mut field_change = ApiChange::member(change, new, EntityKind::FieldDecl, &field.name, values);
+        // private fields only matter to the layout
+        field_change.source_breaking &= field.access != "private";
+        changes.push(field_change);
+    };
+    for field in &old.fields {
+        let Some(matching) = new_fields.get(&field.name) else {
+            field_change(ApiChangeKind::FieldRemoved, field, None);
+            continue;
+        };
+        if field.type_name != matching.type_name {
+            field_change(ApiChangeKind::FieldTypeChanged, matching, Some((field.type_name.clone(), matching.type_name.clone())));
+        }
+        if let (Some(old_offset), Some(new_offset)) = (field.offset, matching.offset) {
+            if old_offset != new_offset {
+                field_change(ApiChangeKind::FieldMoved, matching, Some((old_offset.to_string(), new_offset.to_string())));
+            }
+        }
+    }
+    let old_fields = old.fields.iter().map(|field| &field.name).collect::<HashSet<_>>();
+    for field in new.fields.iter().filter(|field| !old_fields.contains(&field.name)) {
+        field_change(ApiChangeKind::FieldAdded, field, None);
+    }
+}
+
+fn compare_constants(old: &ApiDeclaration, new: &ApiDeclaration, changes: &mut Vec<ApiChange>) {
+    let new_constants = new.constants.iter().map(|constant| (&constant.name, constant)).collect::<HashMap<_, _>>();
+    for constant in &old.constants {
+        let (change, values) = match new_constants.get(&constant.name) {
+            None => (ApiChangeKind::ConstantRemoved, None),
+            Some(matching) if matching.value != constant.value => 
+                (ApiChangeKind::ConstantChanged, Some((constant.get_value(), matching.get_value()))),
+            Some(_) => continue,
+        };
+        changes.push(ApiChange::member(change, new, EntityKind::EnumConstantDecl, &constant.name, values));
+    }
+    let old_constants = old.constants.iter().map(|constant| &constant.name).collect::<HashSet<_>>();
+    for constant in new.constants.iter().filter(|constant| !old_constants.contains(&constant.name)) {
+        changes.push(ApiChange::member(ApiChangeKind::ConstantAdded, new, EntityKind::EnumConstantDecl, &constant.name, None));
+    }
+}
+
+// changes between two declarations matched by `match_declarations`
+fn compare(old: &ApiDeclaration, new: &ApiDeclaration, surfaces: (&ApiSurface, &ApiSurface), changes: &mut Vec<ApiChange>) {
+    let mut push = |change, values: Option<(String, String)>| changes.push(ApiChange::new(change, new, values));
+    if (&old.result, &old.parameters, old.variadic) != (&new.result, &new.parameters, new.variadic) {
+        push(ApiChangeKind::SignatureChanged, Some((old.signature.clone(), new.signature.clone())));
+    }
+    if (old.is_const, old.is_static) != (new.is_const, new.is_static) {
+        push(ApiChangeKind::QualifiersChanged, Some((get_qualifiers(old), get_qualifiers(new))));
+    }
+    match (old.is_virtual, new.is_virtual) {
+        (true, false) => push(ApiChangeKind::VirtualRemoved, None),
+        (false, true) => push(ApiChangeKind::VirtualAdded, None),
+        _ => {},
+    }
+    if !old.is_pure && new.is_pure {
+        push(ApiChangeKind::PureAdded, None);
+    }
+    if get_access_rank(&new.access) > get_access_rank(&old.access) {
+        push(ApiChangeKind::AccessNarrowed, Some((old.access.clone(), new.access.clone())));
+    }
+    for (change, old_value, new_value) in [(ApiChangeKind::SizeChanged, old.size, new.size), 
+        (ApiChangeKind::AlignmentChanged, old.alignment, new.alignment)] {
+        if let (Some(old_value), Some(new_value)) = (old_value, new_value) {
+            if old_value != new_value {
+                push(change, Some((old_value.to_string(), new_value.to_string())));
+            }
+        }
+    }
+    if old.bases != new.bases {
+        push(ApiChangeKind::BasesChanged, Some((old.bases.join(", "), new.bases.join(", "))));
+    }
+    if old.virtual_methods != new.virtual_methods {
+        let get_names = |surface: &ApiSurface, usrs: &[String]| usrs.iter()
+            .map(|usr| surface.declarations.get(usr).map_or(usr.clone(), |method| method.signature.clone()))
+            .collect::<Vec<String>>()
+            .join(", ");
+        push(ApiChangeKind::VtableChanged, Some((get_names(surfaces.0, &old.virtual_methods), get_names(surfaces.1, &new.virtual_methods))));
+    }
+    if let (Some(old_underlying), Some(new_underlying)) = (&old.underlying, &new.underlying) {
+        if old_underlying != new_underlying {
+            push(ApiChangeKind::UnderlyingChanged, Some((old_underlying.clone(), new_underlying.clone())));
+        }
+    }
+    compare_fields(old, new, changes);
+    compare_constants(old, new, changes);
+}
+
+/// Changes between the API surfaces of two snapshots, ordered by name. 
+/// Declarations are matched by USR first, the remaining ones by kind and qualified name if that is unambiguous, 
+/// so that an overload whose parameters changed is reported as changed instead of removed and added.
+pub fn compare_api(old: &ApiSurface, new: &ApiSurface) -> Vec<ApiChange> {
+    let mut changes = Vec::new();
+    let matching = match_declarations(&old.declarations, &new.declarations, 
+        |declaration: &ApiDeclaration| (&declaration.kind, &declaration.name));
+    for (old_declaration, new_declaration) in matching.matched {
+        compare(old_declaration, new_declaration, (old, new), &mut changes);
+    }
+    changes.extend(matching.removed.into_iter().map(|declaration| ApiChange::new(ApiChangeKind::Removed, declaration, None)));
+    changes.extend(matching.added.into_iter().map(|declaration| ApiChange::new(ApiChangeKind::Added, declaration, None)));
+    changes.sort_by(|left, right| (&left.name, left.change, &left.usr).cmp(&(&right.name, right.change, &right.usr)));
+    changes
+}
+
+/// Compatibility report between two API snapshots.
+#[derive(Clone, Debug, Default)]
+pub struct ApiDiff {
+    pub old: String,
+    pub new: String,
+    pub changes: Vec<ApiChange>,
+}
+
+impl WriteDocument for ApiDiff {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line(Key::Old.get_key(), self.old.as_bytes())?;
+        json.render_line(Key::New.get_key(), self.new.as_bytes())?;
+        Array::new(Key::Changes, self.changes.clone()).serialize(json)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+impl ApiDiff {
+    /// Whether any change breaks source or binary compatibility.
+    pub fn is_breaking(&self) -> bool {
+        self.changes.iter().any(|change| change.source_breaking || change.abi_breaking)
+    }
+
+    /// One line per change, marked with what it breaks, followed by the counts.
+    pub fn write_summary<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for change in &self.changes {
+            let breakage = match (change.source_breaking, change.abi_breaking) {
+                (true, true) => "source+abi",
+                (true, false) => "source",
+                (false, true) => "abi",
+                (false, false) => "compatible",
+            };
+            match &change.values {
+                Some((old, new)) => writeln!(out, "{:<10} {} {} {}: {} -> {}", breakage, change.change.get_label(), change.kind, change.name, old, new)?,
+                None => writeln!(out, "{:<10} {} {} {}", breakage, change.change.get_label(), change.kind, change.name)?,
+            }
+        }
+        let count = |breaking: fn(&ApiChange) -> bool| self.changes.iter().filter(|change| breaking(change)).count();
+        writeln!(out, "{} changes, {} source-breaking, {} ABI-breaking", self.changes.len(), 
+            count(|change| change.source_breaking), count(|change| change.abi_breaking))
+    }
+}
+
+/// Compares two `api.json` files, or the ones in two output directories.
+pub fn compare_api_paths(old: &str, new: &str) -> std::result::Result<ApiDiff, Error> {
+    Ok(ApiDiff {
+        old: String::from(old),
+        new: String::from(new),
+        changes: compare_api(&ApiSurface::read(old)?, &ApiSurface::read(new)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn surface(declarations: &str) -> ApiSurface {
+        let api: ApiFile = serde_json::from_str(&format!("{{\"declarations\": [{}]}}", declarations)).unwrap();
+        ApiSurface {
+            declarations: api.declarations.into_iter().map(|declaration| (declaration.usr.clone(), declaration)).collect(),
+        }
+    }
+
+    fn summary(changes: &[ApiChange]) -> Vec<String> {
+        changes.iter().map(|change| format!("{} {} {}/{}", change.change.get_label(), change.name, 
+            change.source_breaking, change.abi_breaking)).collect()
+    }
+
+    #[test]
+    fn changes_are_classified_by_what_they_break() {
+        let old = surface(r#"
+            {"usr": "c:@S@C", "kind": "ClassDecl", "name": "C", "size": 8, "alignment": 4, 
+                "fields": [{"name": "x", "type": "int", "offset": 0, "access": "public"}, 
+                    {"name": "y", "type": "int", "offset": 32, "access": "private"}]},
+            {"usr": "c:@S@C@F@f#", "kind": "Method", "name": "C::f", "access": "public", "signature": "void f()", 
+                "result": "void", "virtual": false},
+            {"usr": "c:@F@g#I#", "kind": "FunctionDecl", "name": "g", "signature": "void g(int)", "result": "void", 
+                "parameters": ["int"]},
+            {"usr": "c:@F@h#", "kind": "FunctionDecl", "name": "h", "signature": "void h()", "result": "void"}"#);
+        let new = surface(r#"
+            {"usr": "c:@S@C", "kind": "ClassDecl", "name": "C", "size": 8, "alignment": 4, 
+                "fields": [{"name": "x", "type": "int", "offset": 0, "access": "public"}, 
+                    {"name": "z", "type": "int", "offset": 32, "access": "private"}]},
+            {"usr": "c:@S@C@F@f#", "kind": "Method", "name": "C::f", "access": "protected", "signature": "void f()", 
+                "result": "void", "virtual": true},
+            {"usr": "c:@F@g#l#", "kind": "FunctionDecl", "name": "g", "signature": "void g(long)", "result": "void", 
+                "parameters": ["long"]},
+            {"usr": "c:@F@k#", "kind": "FunctionDecl", "name": "k", "signature": "void k()", "result": "void"}"#);
+        assert_eq!(summary(&compare_api(&old, &new)), [
+            "virtualAdded C::f false/true",
+            "accessNarrowed C::f true/false",
+            "fieldRemoved C::y false/true",
+            "fieldAdded C::z false/false",
+            "signatureChanged g true/true",
+            "removed h true/true",
+            "added k false/false",
+        ]);
+    }
+
+    #[test]
+    fn constants_compare_over_the_whole_unsigned_range() {
+        let old = surface(r#"{"usr": "c:@E@E", "kind": "EnumDecl", "name": "E", "underlying": "unsigned long", 
+            "constants": [{"name": "Max", "value": 18446744073709551615}, {"name": "Min", "value": 0}, {"name": "Unknown"}]}"#);
+        let new = surface(r#"{"usr": "c:@E@E", "kind": "EnumDecl", "name": "E", "underlying": "unsigned long", 
+            "constants": [{"name": "Max", "value": 18446744073709551614}, {"name": "Min", "value": 0}, {"name": "Unknown", "value": 0}]}"#);
+        let changes = compare_api(&old, &new);
+        assert_eq!(changes.iter().map(|change| (change.name.as_str(), change.values.clone().unwrap())).collect::<Vec<_>>(), [
+            ("E::Max", (String::from("18446744073709551615"), String::from("18446744073709551614"))),
+            ("E::Unknown", (String::from("unknown"), String::from("0"))),
+        ]);
+        let mut json = DocumentSerializer::new(Vec::new());
+        Array::new(Key::Constants, old.declarations["c:@E@E"].constants.clone()).serialize(&mut json).unwrap();
+        let written = String::from_utf8(json.into_inner()).unwrap();
+        assert!(written.contains("18446744073709551615"), "{}", written);
+        assert!(!written.contains("-1"), "{}", written);
+    }
+}
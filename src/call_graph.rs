@@ -0,0 +1,240 @@
+/* 
+   Copyright (c) 2022 ArSysOp.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+  
+       http:  www.apache.org/licenses/LICENSE-2.0
+  
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+  
+   SPDX-License-Identifier: Apache-2.0
+  
+   Contributors:
+     ArSysOp - initial API and implementation
+*/
+
+use clang::{Entity, EntityKind};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::{Result, Write};
+use crate::graph::{visit_ast, should_be_excluded, Exclusion, Array, Key, WriteDocument};
+use crate::json::{Bracket, DocumentSerializer};
+use crate::kind::FUNCTION_KINDS;
+use crate::get_name::*;
+use crate::dot;
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Call {
+    caller: String,
+    callee: String,
+    dynamic: bool,
+}
+
+/// Calls between functions identified by USR. Virtual calls are resolved to the called method 
+/// and all its overriders seen anywhere in the project.
+#[derive(Clone, Debug, Default)]
+pub struct CallGraph {
+    names: BTreeMap<String, String>,
+    calls: BTreeMap<Call, BTreeSet<String>>,
+    overriders: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl CallGraph {
+    fn add_function(&mut self, entity: Entity) -> Option<String> {
+        let usr = get_usr(entity)?;
+        self.names.entry(usr.clone()).or_insert_with(|| return_empty_if_null(entity.get_display_name()));
+        Some(usr)
+    }
+
+    fn record_calls(&mut self, caller: Entity, caller_usr: &str) {
+        // nested functions such as methods of local classes are visited on their own
+        let filter = |entity: Entity| entity == caller || !FUNCTION_KINDS.contains(&entity.get_kind());
+        let recording_task = |entity: Entity| {
+            if entity.get_kind() == EntityKind::CallExpr {
+                if let Some(callee_usr) = entity.get_reference().and_then(|callee| self.add_function(callee)) {
+                    let call = Call { caller: String::from(caller_usr), callee: callee_usr, dynamic: entity.is_dynamic_call() };
+                    self.calls.entry(call).or_default().insert(entity.get_location().get_name());
+                }
+            }
+        };
+        let _ = visit_ast(caller, (recording_task, HashSet::new()), &filter).0;
+    }
+
+    fn record(&mut self, entity: Entity) {
+        if !FUNCTION_KINDS.contains(&entity.get_kind()) {
+            return;
+        }
+        if let Some(usr) = self.add_function(entity) {
+            for overridden in entity.get_overridden_methods().unwrap_or_default() {
+                if let Some(overridden_usr) = self.add_function(overridden) {
+                    self.overriders.entry(overridden_usr).or_default().insert(usr.clone());
+                }
+            }
+            if entity.is_definition() {
+                self.record_calls(entity, &usr);
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: CallGraph) {
+        for (usr, name) in other.names {
+            self.names.entry(usr).or_insert(name);
+        }
+        for (call, locations) in other.calls {
+            self.calls.entry(call).or_default().extend(locations);
+        }
+        for (usr, overriders) in other.overriders {
+            self.overriders.entry(usr).or_default().extend(overriders);
+        }
+    }
+
+    fn get_candidates(&self, call: &Call) -> BTreeSet<String> {
+        let mut candidates = BTreeSet::new();
+        if call.dynamic {
+            let mut pending = vec![call.callee.clone()];
+            while let Some(usr) = pending.pop() {
+                for overrider in self.overriders.get(&usr).into_iter().flatten() {
+                    if candidates.insert(overrider.clone()) {
+                        pending.push(overrider.clone());
+                    }
+                }
+            }
+            // overriders merged from inconsistent translation units may lead back to the callee
+            candidates.remove(&call.callee);
+        }
+        candidates
+    }
+
+    fn get_name(&self, usr: &str) -> String {
+        self.names.get(usr).cloned().unwrap_or_default()
+    }
+
+    pub fn write_dot<W: Write>(&self, out: &mut W) -> Result<()> {
+        dot::render_header(out, "calls")?;
+        for (usr, name) in &self.names {
+            dot::render_node(out, usr, name, "")?;
+        }
+        for call in self.calls.keys() {
+            dot::render_edge(out, &call.caller, &call.callee, if call.dynamic { "style=bold" } else { "" })?;
+            for candidate in self.get_candidates(call) {
+                dot::render_edge(out, &call.caller, &candidate, "style=dashed")?;
+            }
+        }
+        dot::render_footer(out)
+    }
+}
+
+struct CallEntry<'a>(&'a CallGraph, &'a Call, &'a BTreeSet<String>);
+
+impl WriteDocument for CallEntry<'_> {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        let (graph, call, locations) = (self.0, self.1, self.2);
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line(Key::Caller.get_key(), call.caller.as_bytes())?;
+        json.render_line(Key::Callee.get_key(), call.callee.as_bytes())?;
+        json.render_line(Key::Dispatch.get_key(), if call.dynamic { b"virtual" } else { b"static" })?;
+        Array::new(Key::Candidates, graph.get_candidates(call).into_iter().collect()).serialize(json)?;
+        Array::new(Key::CallSites, locations.iter().cloned().collect()).serialize(json)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+impl WriteDocument for CallGraph {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line_without_value(Key::Functions.get_key())?;
+        json.render_bracket(Bracket::LBrace)?;
+        json.prefix.expand();
+        for usr in self.names.keys() {
+            json.render_bracket(Bracket::LCurly)?;
+            json.prefix.expand();
+            json.render_line(Key::Usr.get_key(), usr.as_bytes())?;
+            json.render_line(Key::Name.get_key(), self.get_name(usr).as_bytes())?;
+            json.prefix.shrink();
+            json.render_bracket(Bracket::RCurly)?;
+        }
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RBrace)?;
+        json.render_line_without_value(Key::Calls.get_key())?;
+        json.render_bracket(Bracket::LBrace)?;
+        json.prefix.expand();
+        for (call, locations) in &self.calls {
+            CallEntry(self, call, locations).serialize(json)?;
+        }
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RBrace)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+pub fn collect_calls(parent: Entity, exclusion: &Exclusion) -> CallGraph {
+    let mut graph = CallGraph::default();
+    let recording_task = |entity: Entity| graph.record(entity);
+    let filter = |entity: Entity| !should_be_excluded(entity, exclusion);
+    let _ = visit_ast(parent, (recording_task, HashSet::new()), &filter).0;
+    graph
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // overriders of every method, given as (overridden, overrider) pairs
+    fn graph(overrides: &[(&str, &str)]) -> CallGraph {
+        let mut graph = CallGraph::default();
+        for (overridden, overrider) in overrides {
+            graph.overriders.entry(String::from(*overridden)).or_default().insert(String::from(*overrider));
+        }
+        graph
+    }
+
+    fn call(callee: &str, dynamic: bool) -> Call {
+        Call { caller: String::from("main"), callee: String::from(callee), dynamic }
+    }
+
+    fn candidates(graph: &CallGraph, callee: &str) -> Vec<String> {
+        graph.get_candidates(&call(callee, true)).into_iter().collect()
+    }
+
+    #[test]
+    fn virtual_call_has_the_overriders_of_every_level() {
+        let graph = graph(&[("A::f", "B::f"), ("B::f", "C::f"), ("C::f", "D::f")]);
+        assert_eq!(candidates(&graph, "A::f"), ["B::f", "C::f", "D::f"]);
+        assert_eq!(candidates(&graph, "C::f"), ["D::f"]);
+        assert!(graph.get_candidates(&call("A::f", false)).is_empty());
+    }
+
+    #[test]
+    fn overrider_reached_twice_is_a_single_candidate() {
+        let graph = graph(&[("A::f", "B::f"), ("A::f", "C::f"), ("B::f", "D::f"), ("C::f", "D::f")]);
+        assert_eq!(candidates(&graph, "A::f"), ["B::f", "C::f", "D::f"]);
+    }
+
+    #[test]
+    fn cycle_of_overriders_ends_without_the_callee() {
+        let graph = graph(&[("A::f", "B::f"), ("B::f", "C::f"), ("C::f", "A::f")]);
+        assert_eq!(candidates(&graph, "A::f"), ["B::f", "C::f"]);
+        assert_eq!(candidates(&graph, "B::f"), ["A::f", "C::f"]);
+    }
+
+    #[test]
+    fn merged_graphs_chain_the_overriders_of_both() {
+        let mut merged = graph(&[("A::f", "B::f")]);
+        merged.calls.entry(call("A::f", true)).or_default().insert(String::from("a.cpp:5:3"));
+        let mut other = graph(&[("B::f", "C::f"), ("A::f", "B::f")]);
+        other.calls.entry(call("A::f", true)).or_default().insert(String::from("b.cpp:7:3"));
+        merged.merge(other);
+        assert_eq!(candidates(&merged, "A::f"), ["B::f", "C::f"]);
+        assert_eq!(merged.overriders["A::f"].len(), 1);
+        assert_eq!(merged.calls[&call("A::f", true)].iter().collect::<Vec<_>>(), ["a.cpp:5:3", "b.cpp:7:3"]);
+    }
+}
@@ -0,0 +1,123 @@
+/* 
+   Copyright (c) 2022 ArSysOp.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+  
+       http:  www.apache.org/licenses/LICENSE-2.0
+  
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+  
+   SPDX-License-Identifier: Apache-2.0
+  
+   Contributors:
+     ArSysOp - initial API and implementation
+*/
+
+use std::collections::HashSet;
+use std::fs;
+use clang::CompilationDatabase;
+use glob::Pattern;
+use crate::parse_cpp::Input;
+use crate::error::Error;
+use crate::output::normalize_path;
+
+// `-o` followed by the output file or glued to it, but not clang options such as `-objcmt-migrate-all`
+fn is_glued_output(argument: &str) -> bool {
+    argument.len() > 2 && argument.starts_with("-o") && !argument.starts_with("-obj")
+}
+
+fn strip_arguments(arguments: Vec<String>, file: &str, path: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    // the first argument is the compiler itself
+    let mut arguments = arguments.into_iter().skip(1);
+    while let Some(argument) = arguments.next() {
+        if argument == "-o" {
+            arguments.next();
+        } else if !is_glued_output(&argument) && argument != "-c" && argument != "--" && argument != file && argument != path {
+            result.push(argument);
+        }
+    }
+    result
+}
+
+fn matches_filters(path: &str, filters: &[Pattern]) -> bool {
+    filters.is_empty() || filters.iter().any(|filter| filter.matches(path))
+}
+
+// a file listed several times, e.g. once per configuration or target, would be written to the same output files 
+// by several workers, only its first entry is kept
+fn deduplicate(inputs: Vec<Input>) -> Vec<Input> {
+    let mut seen = HashSet::new();
+    inputs.into_iter()
+    .filter(|input| seen.insert(fs::canonicalize(&input.path).unwrap_or_else(|_| normalize_path(&input.path))))
+    .collect()
+}
+
+/// Inputs of the `compile_commands.json` in the directory with their arguments and working directories,
+/// only the ones matching one of the glob patterns if any are given. A file listed several times is parsed 
+/// with the arguments of its first entry.
+pub fn read_compilation_database(database_dir: String, filters: Vec<String>) -> Result<Vec<Input>, Error> {
+    let database = CompilationDatabase::from_directory(&database_dir)
+    .map_err(|_| Error::CompilationDatabase(database_dir.clone()))?;
+    let filters = filters.iter()
+    .map(|filter| Pattern::new(filter).map_err(|err| Error::Pattern(filter.clone(), err)))
+    .collect::<Result<Vec<Pattern>, Error>>()?;
+    let mut result = Vec::new();
+    for command in database.get_all_compile_commands().get_commands() {
+        let directory = command.get_directory();
+        let file = command.get_filename();
+        let path = directory.join(&file).to_string_lossy().replace('\\', "/");
+        if matches_filters(&path, &filters) {
+            result.push(Input {
+                parse_options: strip_arguments(command.get_arguments(), &file.to_string_lossy(), &path),
+                working_dir: Some(directory.to_string_lossy().to_string()),
+                path,
+            });
+        }
+    }
+    Ok(deduplicate(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(path: &str, option: &str) -> Input {
+        Input { path: String::from(path), parse_options: vec![String::from(option)], working_dir: None }
+    }
+
+    #[test]
+    fn compiler_output_and_input_are_stripped_from_arguments() {
+        let cases: [(&[&str], &str, &str, &[&str]); 6] = [
+            (&["clang++", "-c", "a.cpp", "-o", "a.o"], "a.cpp", "/build/a.cpp", &[]),
+            (&["clang++", "-DX", "-oa.o", "-c", "a.cpp"], "a.cpp", "/build/a.cpp", &["-DX"]),
+            (&["g++", "-Iinclude", "-c", "/build/a.cpp", "-o", "out/a.o", "-O2"], "/build/a.cpp", "/build/a.cpp", &["-Iinclude", "-O2"]),
+            // the file relative to the directory of the entry
+            (&["c++", "-std=c++17", "-c", "src/a.cpp", "-oobj/a.o"], "src/a.cpp", "/build/src/a.cpp", &["-std=c++17"]),
+            (&["c++", "-c", "/build/src/a.cpp", "-Wall"], "src/a.cpp", "/build/src/a.cpp", &["-Wall"]),
+            (&["clang++", "-objcmt-migrate-all", "-O0", "--", "a.cpp"], "a.cpp", "/build/a.cpp", &["-objcmt-migrate-all", "-O0"]),
+        ];
+        for (arguments, file, path, expected) in cases {
+            let arguments = arguments.iter().map(|argument| String::from(*argument)).collect();
+            assert_eq!(strip_arguments(arguments, file, path), expected, "arguments of {}", file);
+        }
+    }
+
+    #[test]
+    fn file_listed_several_times_is_kept_once_with_its_first_entry() {
+        let inputs = deduplicate(vec![
+            input("/build/src/a.cpp", "-DDEBUG"),
+            input("/build/src/b.cpp", "-DDEBUG"),
+            input("/build/src/./a.cpp", "-DRELEASE"),
+            input("/build/tests/../src/a.cpp", "-DTEST"),
+        ]);
+        let kept = inputs.iter().map(|input| (input.path.as_str(), input.parse_options[0].as_str())).collect::<Vec<_>>();
+        assert_eq!(kept, [("/build/src/a.cpp", "-DDEBUG"), ("/build/src/b.cpp", "-DDEBUG")]);
+    }
+}
@@ -0,0 +1,257 @@
+/* 
+   Copyright (c) 2022 ArSysOp.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+  
+       http:  www.apache.org/licenses/LICENSE-2.0
+  
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+  
+   SPDX-License-Identifier: Apache-2.0
+  
+   Contributors:
+     ArSysOp - initial API and implementation
+*/
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use rusqlite::{params_from_iter, types::Value, Connection, Statement, Transaction};
+use crate::error::Error;
+use crate::graph::{Key, TableNodeWriter, AST, EDGE_COLUMNS, NODE_COLUMNS, TYPE_COLUMNS};
+use crate::diagnostics::{DiagnosticInfo, DIAGNOSTIC_COLUMNS};
+use crate::index::{SymbolIndex, SYMBOL_COLUMNS};
+use crate::json::{Cell, TableWriter};
+
+const NODES_TABLE: &str = "nodes";
+const TYPES_TABLE: &str = "types";
+const REFERENCES_TABLE: &str = "references";
+const DIAGNOSTICS_TABLE: &str = "diagnostics";
+const SYMBOLS_TABLE: &str = "symbols";
+// every table except symbols has a leading fileId column
+const FILE_ID_COLUMN: &str = "fileId";
+const PER_FILE_TABLES: [&str; 4] = [NODES_TABLE, TYPES_TABLE, REFERENCES_TABLE, DIAGNOSTICS_TABLE];
+// rows of a table kept by a worker before they are inserted, the database is only locked to insert them
+const BATCH_ROWS: usize = 10_000;
+
+// created once all rows are inserted, maintaining them row by row is much slower
+const INDEXES: &str = "
+CREATE INDEX nodes_usr ON nodes (usr);
+CREATE INDEX nodes_location ON nodes (location);
+CREATE INDEX nodes_id ON nodes (fileId, id);
+CREATE INDEX types_id ON types (fileId, id);
+CREATE INDEX references_source ON \"references\" (fileId, source);
+CREATE INDEX references_usr ON \"references\" (usr);
+CREATE INDEX diagnostics_location ON diagnostics (location);
+CREATE UNIQUE INDEX symbols_usr ON symbols (usr);
+";
+
+fn get_columns(columns: &[Key], per_file: bool) -> Vec<String> {
+    let per_file = per_file.then(|| String::from(FILE_ID_COLUMN));
+    per_file.into_iter()
+    .chain(columns.iter().map(|column| String::from_utf8_lossy(column.get_key()).to_string()))
+    .collect()
+}
+
+fn create_table(connection: &Connection, name: &str, columns: &[Key], per_file: bool) -> rusqlite::Result<()> {
+    connection.execute(&format!("CREATE TABLE \"{}\" ({})", name, get_columns(columns, per_file).join(", ")), [])?;
+    Ok(())
+}
+
+/// Inserts the rows of one table, prefixed with the file ID for per-file tables.
+struct DatabaseTable<'a> {
+    statement: Statement<'a>,
+    file_id: Option<i64>,
+}
+
+impl DatabaseTable<'_> {
+    fn new<'a>(transaction: &'a Transaction, name: &str, columns: &[Key], file_id: Option<i64>) -> rusqlite::Result<DatabaseTable<'a>> {
+        let placeholders = vec!["?"; get_columns(columns, file_id.is_some()).len()].join(", ");
+        let statement = transaction.prepare(&format!("INSERT INTO \"{}\" VALUES ({})", name, placeholders))?;
+        Ok(DatabaseTable { statement, file_id })
+    }
+
+    fn get_row(file_id: Option<i64>, cells: &[Cell]) -> Vec<Value> {
+        file_id.map(Value::Integer).into_iter()
+        .chain(cells.iter().map(|cell| match cell {
+            Cell::Text(text) => Value::Text(String::from_utf8_lossy(text).to_string()),
+            Cell::Number(number) => Value::Integer(*number as i64),
+            Cell::Bool(value) => Value::Integer(*value as i64),
+            Cell::Null => Value::Null,
+        }))
+        .collect()
+    }
+
+    fn insert(&mut self, row: Vec<Value>) -> io::Result<()> {
+        self.statement.execute(params_from_iter(row)).map_err(io::Error::other)?;
+        Ok(())
+    }
+}
+
+impl TableWriter for DatabaseTable<'_> {
+    fn render_row(&mut self, cells: &[Cell]) -> io::Result<()> {
+        self.insert(DatabaseTable::get_row(self.file_id, cells))
+    }
+}
+
+/// Rows of one per-file table of a translation unit, kept by the worker until a batch is complete. 
+/// Each batch is inserted in its own transaction, so workers only wait for each other while rows are inserted.
+struct BatchTable<'a> {
+    database: &'a Database,
+    name: &'static str,
+    columns: &'static [Key],
+    file_id: i64,
+    rows: Vec<Vec<Value>>,
+}
+
+impl BatchTable<'_> {
+    fn new<'a>(database: &'a Database, name: &'static str, columns: &'static [Key], file_id: i64) -> BatchTable<'a> {
+        BatchTable { database, name, columns, file_id, rows: Vec::new() }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let rows = std::mem::take(&mut self.rows);
+        if rows.is_empty() {
+            return Ok(());
+        }
+        self.database.run_transaction(|transaction| {
+            let mut table = DatabaseTable::new(transaction, self.name, self.columns, Some(self.file_id)).map_err(io::Error::other)?;
+            rows.into_iter().try_for_each(|row| table.insert(row))
+        })
+    }
+}
+
+impl TableWriter for BatchTable<'_> {
+    fn render_row(&mut self, cells: &[Cell]) -> io::Result<()> {
+        self.rows.push(DatabaseTable::get_row(Some(self.file_id), cells));
+        match self.rows.len() >= BATCH_ROWS {
+            true => self.flush(),
+            false => Ok(()),
+        }
+    }
+}
+
+/// SQLite database receiving the ASTs of all translation units, shared by the workers. 
+/// Files are identified by their position in the input list.
+pub struct Database {
+    path: String,
+    connection: Mutex<Connection>,
+}
+
+impl Database {
+    pub fn create(path: &str, inputs: &[String]) -> Result<Database, Error> {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent).map_err(|err| Error::Io(parent.to_string_lossy().to_string(), err))?;
+        }
+        // rows left by a previous run would mix with the new ones
+        if Path::new(path).exists() {
+            fs::remove_file(path).map_err(|err| Error::Io(String::from(path), err))?;
+        }
+        let database_error = |err| Error::Database(String::from(path), err);
+        let mut connection = Connection::open(path).map_err(database_error)?;
+        connection.execute("CREATE TABLE files (id INTEGER PRIMARY KEY, path TEXT)", []).map_err(database_error)?;
+        create_table(&connection, NODES_TABLE, &NODE_COLUMNS, true).map_err(database_error)?;
+        create_table(&connection, TYPES_TABLE, &TYPE_COLUMNS, true).map_err(database_error)?;
+        create_table(&connection, REFERENCES_TABLE, &EDGE_COLUMNS, true).map_err(database_error)?;
+        create_table(&connection, DIAGNOSTICS_TABLE, &DIAGNOSTIC_COLUMNS, true).map_err(database_error)?;
+        create_table(&connection, SYMBOLS_TABLE, &SYMBOL_COLUMNS, false).map_err(database_error)?;
+        let transaction = connection.transaction().map_err(database_error)?;
+        for (position, input) in inputs.iter().enumerate() {
+            transaction.execute("INSERT INTO files VALUES (?, ?)", (position as i64, input)).map_err(database_error)?;
+        }
+        transaction.commit().map_err(database_error)?;
+        Ok(Database { path: String::from(path), connection: Mutex::new(connection) })
+    }
+
+    // errors are reported by the caller, with the path of the database
+    fn run_transaction<F: FnOnce(&Transaction) -> io::Result<()>>(&self, write_rows: F) -> io::Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(io::Error::other)?;
+        write_rows(&transaction)?;
+        transaction.commit().map_err(io::Error::other)
+    }
+
+    fn write<F: FnOnce(&Transaction) -> io::Result<()>>(&self, write_rows: F) -> Result<(), Error> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(|err| Error::Database(self.path.clone(), err))?;
+        write_rows(&transaction).map_err(|err| Error::Io(self.path.clone(), err))?;
+        transaction.commit().map_err(|err| Error::Database(self.path.clone(), err))
+    }
+
+    /// Writes the nodes, types, references and diagnostics of one translation unit. 
+    /// Rows are extracted while the AST is traversed and inserted in batches, the database is only locked for each batch. 
+    /// If a batch fails, the rows already inserted for the translation unit are deleted.
+    pub fn write_translation_unit(&self, file_id: usize, ast: &AST, diagnostics: &[DiagnosticInfo]) -> Result<(), Error> {
+        let file_id = file_id as i64;
+        let mut tables = TableNodeWriter {
+            nodes: BatchTable::new(self, NODES_TABLE, &NODE_COLUMNS, file_id),
+            edges: BatchTable::new(self, REFERENCES_TABLE, &EDGE_COLUMNS, file_id),
+            types: BatchTable::new(self, TYPES_TABLE, &TYPE_COLUMNS, file_id),
+        };
+        let mut rows = BatchTable::new(self, DIAGNOSTICS_TABLE, &DIAGNOSTIC_COLUMNS, file_id);
+        let written = ast.write(&mut tables)
+        .and_then(|_| diagnostics.iter().try_for_each(|diagnostic| diagnostic.write_row(&mut rows)))
+        .and_then(|_| [&mut tables.nodes, &mut tables.edges, &mut tables.types, &mut rows].into_iter().try_for_each(BatchTable::flush));
+        if let Err(err) = written {
+            // the error of the batch is the one worth reporting
+            let _ = self.delete_translation_unit(file_id);
+            return Err(Error::Io(self.path.clone(), err));
+        }
+        Ok(())
+    }
+
+    fn delete_translation_unit(&self, file_id: i64) -> io::Result<()> {
+        self.run_transaction(|transaction| PER_FILE_TABLES.iter().try_for_each(|table| {
+            transaction.execute(&format!("DELETE FROM \"{}\" WHERE {} = ?", table, FILE_ID_COLUMN), [file_id])
+            .map(|_| ())
+            .map_err(io::Error::other)
+        }))
+    }
+
+    /// Writes the project-wide symbols and creates the indexes, nothing is written after it.
+    pub fn finish(self, symbols: &SymbolIndex) -> Result<(), Error> {
+        self.write(|transaction| {
+            let mut rows = DatabaseTable::new(transaction, SYMBOLS_TABLE, &SYMBOL_COLUMNS, None).map_err(io::Error::other)?;
+            symbols.write_rows(&mut rows)
+        })?;
+        let connection = self.connection.into_inner().unwrap();
+        connection.execute_batch(INDEXES).map_err(|err| Error::Database(self.path, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count_rows(database: &Database, table: &str) -> i64 {
+        let connection = database.connection.lock().unwrap();
+        connection.query_row(&format!("SELECT COUNT(*) FROM \"{}\"", table), [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn rows_are_inserted_in_batches_and_deleted_per_file() {
+        let path = std::env::temp_dir().join(format!("loft-cpp-rust-{}.sqlite", std::process::id()));
+        let path = path.to_string_lossy();
+        let database = Database::create(&path, &[String::from("a.cpp"), String::from("b.cpp")]).unwrap();
+        for file_id in 0..2 {
+            let mut table = BatchTable::new(&database, TYPES_TABLE, &TYPE_COLUMNS, file_id);
+            for id in 0..BATCH_ROWS as u64 + 1 {
+                table.render_row(&[Cell::Number(id), Cell::Text(b"int"), Cell::Text(b"int"), Cell::Text(b"Int")]).unwrap();
+            }
+            // a full batch is inserted right away, the rest on flush
+            assert_eq!(count_rows(&database, TYPES_TABLE), (file_id + 1) * BATCH_ROWS as i64 + file_id);
+            table.flush().unwrap();
+        }
+        database.delete_translation_unit(0).unwrap();
+        assert_eq!(count_rows(&database, TYPES_TABLE), BATCH_ROWS as i64 + 1);
+        database.finish(&SymbolIndex::default()).unwrap();
+        fs::remove_file(path.as_ref()).unwrap();
+    }
+}
@@ -0,0 +1,259 @@
+/* 
+   Copyright (c) 2022 ArSysOp.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+  
+       http:  www.apache.org/licenses/LICENSE-2.0
+  
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+  
+   SPDX-License-Identifier: Apache-2.0
+  
+   Contributors:
+     ArSysOp - initial API and implementation
+*/
+
+use std::ffi::CStr;
+use std::io::{Result, Write};
+use std::{mem, ptr};
+use clang::TranslationUnit;
+use clang::diagnostic::{Diagnostic, FixIt};
+use clang::source::SourceRange;
+use clang_sys::{clang_disposeString, clang_getCString, clang_getDiagnosticCategoryText, clang_getDiagnosticOption, 
+    CXDiagnostic, CXString};
+use crate::json::{Bracket, Cell, DocumentSerializer, TableWriter};
+use crate::graph::{serialize_array, Array, Key, Range, WriteDocument};
+use crate::kind::get_severity_label;
+use crate::get_name::Name;
+
+/// Change of the source suggested by a diagnostic.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FixItInfo {
+    /// `deletion`, `insertion` or `replacement`.
+    pub kind: String,
+    pub range: Range,
+    pub text: String,
+}
+
+impl WriteDocument for FixItInfo {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line(Key::Kind.get_key(), self.kind.as_bytes())?;
+        json.render_line_without_value(Key::Range.get_key())?;
+        self.range.serialize(json)?;
+        json.render_line(Key::Text.get_key(), self.text.as_bytes())?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+impl FixItInfo {
+    fn new(fix_it: FixIt) -> FixItInfo {
+        let (kind, range, text) = match fix_it {
+            FixIt::Deletion(range) => ("deletion", range, String::from("")),
+            FixIt::Insertion(location, text) => ("insertion", SourceRange::new(location, location), text),
+            FixIt::Replacement(range, text) => ("replacement", range, text),
+        };
+        FixItInfo {
+            kind: String::from(kind),
+            range: Range::new(range),
+            text,
+        }
+    }
+}
+
+/// Clang diagnostic of a translation unit, with its notes and fix-its.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticInfo {
+    pub severity: String,
+    pub location: String,
+    pub message: String,
+    pub category: String,
+    pub option: String,
+    pub ranges: Vec<Range>,
+    pub fix_its: Vec<FixItInfo>,
+    pub notes: Vec<DiagnosticInfo>,
+}
+
+impl WriteDocument for DiagnosticInfo {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        for (key, value) in self.get_attributes() {
+            json.render_line(key.get_key(), value.as_bytes())?;
+        }
+        serialize_array(Key::Ranges, &self.ranges, json)?;
+        serialize_array(Key::FixIts, &self.fix_its, json)?;
+        serialize_array(Key::Notes, &self.notes, json)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+// the clang crate has no accessor for the category and the option of a diagnostic, nor for its `CXDiagnostic`: 
+// a `Diagnostic` is made of that pointer and of a reference to its translation unit, so the pointer is the word 
+// that is not the translation unit, confirmed by comparing the diagnostic built from it, which compares pointers
+fn get_raw_diagnostic(diagnostic: &Diagnostic, tu: &TranslationUnit) -> Option<CXDiagnostic> {
+    if mem::size_of::<Diagnostic>() != 2 * mem::size_of::<usize>() {
+        return None;
+    }
+    // SAFETY: `Diagnostic` is `Copy` and holds two initialized pointers, so it has no padding
+    let words: [usize; 2] = unsafe { mem::transmute_copy(diagnostic) };
+    words.into_iter()
+    .filter(|word| *word != 0 && *word != tu as *const TranslationUnit as usize)
+    .map(|word| word as CXDiagnostic)
+    .find(|raw| Diagnostic::from_ptr(*raw, tu) == *diagnostic)
+}
+
+// SAFETY: the string must come from libclang and not be disposed yet, it is disposed here
+unsafe fn into_string(string: CXString) -> String {
+    let text = clang_getCString(string);
+    let result = match text.is_null() {
+        true => String::new(),
+        false => CStr::from_ptr(text).to_string_lossy().to_string(),
+    };
+    clang_disposeString(string);
+    result
+}
+
+// category name like `Semantic Issue` and the option enabling the diagnostic like `-Wunused-variable`, empty if none
+fn get_category_and_option(diagnostic: &Diagnostic, tu: &TranslationUnit) -> (String, String) {
+    match get_raw_diagnostic(diagnostic, tu) {
+        // SAFETY: the diagnostic belongs to the translation unit, which outlives this call
+        Some(raw) => unsafe { (into_string(clang_getDiagnosticCategoryText(raw)), into_string(clang_getDiagnosticOption(raw, ptr::null_mut()))) },
+        None => (String::new(), String::new()),
+    }
+}
+
+pub const DIAGNOSTIC_COLUMNS: [Key; 5] = [Key::Severity, Key::Location, Key::Message, Key::Category, Key::Option];
+
+impl DiagnosticInfo {
+    /// Writes the diagnostic as a row, without its ranges, fix-its and notes.
+    pub fn write_row<T: TableWriter>(&self, diagnostics: &mut T) -> Result<()> {
+        diagnostics.render_row(&self.get_attributes().map(|(_, value)| Cell::Text(value.as_bytes())))
+    }
+
+    // in the order of `DIAGNOSTIC_COLUMNS`
+    fn get_attributes(&self) -> [(Key, &String); 5] {
+        [
+            (Key::Severity, &self.severity),
+            (Key::Location, &self.location),
+            (Key::Message, &self.message),
+            (Key::Category, &self.category),
+            (Key::Option, &self.option),
+        ]
+    }
+
+    pub(crate) fn new(diagnostic: Diagnostic, tu: &TranslationUnit) -> DiagnosticInfo {
+        let (category, option) = get_category_and_option(&diagnostic, tu);
+        DiagnosticInfo {
+            severity: get_severity_label(diagnostic.get_severity()),
+            location: diagnostic.get_location().get_name(),
+            message: diagnostic.get_text(),
+            category,
+            option,
+            ranges: diagnostic.get_ranges().into_iter().map(Range::new).collect(),
+            fix_its: diagnostic.get_fix_its().into_iter().map(FixItInfo::new).collect(),
+            notes: diagnostic.get_children().into_iter().map(|note| DiagnosticInfo::new(note, tu)).collect(),
+        }
+    }
+}
+
+pub fn collect_diagnostics(tu: &TranslationUnit) -> Vec<DiagnosticInfo> {
+    tu.get_diagnostics().into_iter().map(|diagnostic| DiagnosticInfo::new(diagnostic, tu)).collect()
+}
+
+/// Top-level object of a diagnostics sidecar file.
+pub struct DiagnosticsFile {
+    file: (Key, String),
+    diagnostics: Array<DiagnosticInfo>,
+}
+
+impl WriteDocument for DiagnosticsFile {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line(self.file.0.get_key(), self.file.1.as_bytes())?;
+        self.diagnostics.serialize(json)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+impl DiagnosticsFile {
+    pub fn new(path: String, diagnostics: Vec<DiagnosticInfo>) -> DiagnosticsFile {
+        DiagnosticsFile {
+            file: (Key::File, path),
+            diagnostics: Array::new(Key::Diagnostics, diagnostics),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{Position, RangeEnd};
+    use crate::json::{TableFormat, TableSerializer};
+
+    fn range(line: u32) -> Range {
+        let end = RangeEnd {
+            spelling: Position { file: String::from("a.cpp"), line, column: 5, offset: Some(20) },
+            expansion: Position { file: String::from("a.cpp"), line, column: 5, offset: Some(20) },
+            presumed: Position { file: String::from("a.cpp"), line, column: 5, offset: None },
+        };
+        Range { begin: end.clone(), end }
+    }
+
+    fn diagnostic() -> DiagnosticInfo {
+        DiagnosticInfo {
+            severity: String::from("warning"),
+            location: String::from("a.cpp:3:5"),
+            message: String::from("unused variable 'x'"),
+            category: String::from("Semantic Issue"),
+            option: String::from("-Wunused-variable"),
+            ranges: vec![range(3)],
+            fix_its: vec![FixItInfo { kind: String::from("deletion"), range: range(3), text: String::new() }],
+            notes: vec![DiagnosticInfo {
+                severity: String::from("note"),
+                location: String::from("a.cpp:1:1"),
+                message: String::from("declared \"here\""),
+                category: String::new(),
+                option: String::new(),
+                ranges: Vec::new(),
+                fix_its: Vec::new(),
+                notes: Vec::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn diagnostics_file_is_written_as_serde_reads_it() {
+        let mut json = DocumentSerializer::new(Vec::new());
+        DiagnosticsFile::new(String::from("a.cpp"), vec![diagnostic()]).serialize(&mut json).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&json.into_inner()).unwrap();
+        assert_eq!(value["file"], "a.cpp");
+        let diagnostics: Vec<DiagnosticInfo> = serde_json::from_value(value["diagnostics"].clone()).unwrap();
+        assert_eq!(diagnostics, [diagnostic()]);
+    }
+
+    #[test]
+    fn row_has_the_diagnostic_columns_without_ranges_fix_its_and_notes() {
+        let columns = DIAGNOSTIC_COLUMNS.iter().map(Key::get_key).collect();
+        let mut table = TableSerializer::new(Vec::new(), TableFormat::Csv, columns).unwrap();
+        diagnostic().write_row(&mut table).unwrap();
+        diagnostic().notes[0].write_row(&mut table).unwrap();
+        assert_eq!(String::from_utf8(table.into_inner()).unwrap(), concat!(
+            "severity,location,message,category,option\r\n",
+            "warning,a.cpp:3:5,unused variable 'x',Semantic Issue,-Wunused-variable\r\n",
+            "note,a.cpp:1:1,\"declared \"\"here\"\"\",,\r\n",
+        ));
+    }
+}
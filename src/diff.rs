@@ -0,0 +1,454 @@
+/* 
+   Copyright (c) 2022 ArSysOp.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+  
+       http:  www.apache.org/licenses/LICENSE-2.0
+  
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+  
+   SPDX-License-Identifier: Apache-2.0
+  
+   Contributors:
+     ArSysOp - initial API and implementation
+*/
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::Hash;
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+use crate::error::Error;
+use crate::graph::{Array, Key, Link, WriteDocument, Tree};
+use crate::json::{Bracket, DocumentSerializer};
+use crate::kind::{get_kind_label, FUNCTION_KINDS};
+use crate::output::{common_root, normalize_path, read_output_index};
+use crate::reader::read_tree_file;
+
+// kinds of the nodes compared between revisions, parameters belong to the signature of their function 
+// and what is declared in its body is left out
+const DECLARATION_KINDS: [&str; 20] = ["StructDecl", "UnionDecl", "ClassDecl", "EnumDecl", "FieldDecl", "EnumConstantDecl", 
+    "FunctionDecl", "VarDecl", "TypedefDecl", "Method", "Namespace", "Constructor", "Destructor", "ConversionFunction", 
+    "FunctionTemplate", "ClassTemplate", "ClassTemplatePartialSpecialization", "NamespaceAlias", "TypeAliasDecl", 
+    "TypeAliasTemplateDecl"];
+
+fn is_function(kind: &str) -> bool {
+    FUNCTION_KINDS.iter().any(|function_kind| get_kind_label(*function_kind) == kind)
+}
+
+#[derive(Clone, Debug)]
+struct Declaration {
+    kind: String,
+    // names of the enclosing declarations joined with `::`
+    name: String,
+    usr: String,
+    // key of the enclosing declaration, empty at the top level
+    parent: String,
+    parent_name: String,
+    type_name: Option<String>,
+    // `file:line:column` relative to the root of its revision, of the definition if there is one
+    location: String,
+    definition: bool,
+}
+
+impl Declaration {
+    fn get_file(&self) -> &str {
+        self.location.rsplitn(3, ':').last().unwrap_or_default()
+    }
+
+    fn get_signature(&self) -> String {
+        self.type_name.clone().unwrap_or_default()
+    }
+
+    fn get_structural_key(&self) -> (String, String, String) {
+        (self.parent.clone(), self.kind.clone(), self.name.clone())
+    }
+}
+
+/// Declarations of one revision by USR, or by their position below the enclosing declaration if they have none. 
+/// Declarations repeated in several trees, e.g. from a shared header, are kept once.
+#[derive(Clone, Debug, Default)]
+pub struct Declarations {
+    declarations: BTreeMap<String, Declaration>,
+}
+
+// locations of the two revisions are compared relative to their roots, which usually are different checkouts
+fn get_relative_location(location: &str, root: &Path) -> String {
+    let mut parts = location.rsplitn(3, ':');
+    let (column, line, file) = (parts.next(), parts.next(), parts.next());
+    match (file, line, column) {
+        (Some(file), Some(line), Some(column)) if !file.is_empty() => {
+            let path = normalize_path(file);
+            let file = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+            format!("{}:{}:{}", file, line, column)
+        },
+        _ => String::from(location),
+    }
+}
+
+impl Declarations {
+    /// Adds the declarations of the tree, with locations relative to `root`. 
+    /// Local variables and types of a function, which no other file can refer to, are left out.
+    pub fn add_tree(&mut self, tree: &Tree, root: &Path) {
+        // key and name of every enclosing declaration, the translation unit first
+        let mut scopes = vec![(String::new(), String::new())];
+        let mut pending = vec![(tree, 0)];
+        while let Some((tree, scope)) = pending.pop() {
+            let node = &tree.node;
+            let mut inner = scope;
+            if DECLARATION_KINDS.contains(&node.kind.as_str()) {
+                let (parent, parent_name) = scopes[scope].clone();
+                let simple_name = if node.name.is_empty() { "(anonymous)" } else { node.name.as_str() };
+                let name = match parent_name.is_empty() {
+                    true => String::from(simple_name),
+                    false => format!("{}::{}", parent_name, simple_name),
+                };
+                let key = match node.usr.is_empty() {
+                    true => format!("{}/{} {}", parent, node.kind, name),
+                    false => node.usr.clone(),
+                };
+                let definition = node.definition == Some(Link::Id(node.id));
+                let location = get_relative_location(&node.location, root);
+                match self.declarations.get_mut(&key) {
+                    Some(existing) => if definition && !existing.definition {
+                        existing.location = location;
+                        existing.definition = true;
+                    },
+                    None => {
+                        self.declarations.insert(key.clone(), Declaration {
+                            kind: node.kind.clone(),
+                            name: name.clone(),
+                            usr: node.usr.clone(),
+                            parent,
+                            parent_name,
+                            type_name: node.type_.as_ref().map(|type_info| type_info.display_name.clone()),
+                            location,
+                            definition,
+                        });
+                    },
+                }
+                scopes.push((key, name));
+                inner = scopes.len() - 1;
+            }
+            if is_function(&node.kind) {
+                continue;
+            }
+            pending.extend(tree.children.iter().rev().map(|child| (child, inner)));
+        }
+    }
+}
+
+/// What changed in a declaration between two revisions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    Removed,
+    Added,
+    /// The type of a function, i.e. its parameters or its result, changed.
+    SignatureChanged,
+    /// The type of any other declaration changed.
+    TypeChanged,
+    /// The declaration, or its definition, is in another file.
+    Moved,
+}
+
+impl ChangeKind {
+    /// Name of the change in the JSON report, e.g. `signatureChanged`.
+    pub fn get_label(&self) -> &'static str {
+        match self {
+            ChangeKind::Removed => "removed",
+            ChangeKind::Added => "added",
+            ChangeKind::SignatureChanged => "signatureChanged",
+            ChangeKind::TypeChanged => "typeChanged",
+            ChangeKind::Moved => "moved",
+        }
+    }
+}
+
+/// One semantic change of a declaration. Members of classes and namespaces name their parent.
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub change: ChangeKind,
+    pub kind: String,
+    pub name: String,
+    /// The USR in the new revision, unless the declaration was removed.
+    pub usr: String,
+    pub parent: String,
+    /// The location in the new revision, unless the declaration was removed.
+    pub location: String,
+    /// Signature, type or file before and after the change.
+    pub values: Option<(String, String)>,
+}
+
+impl WriteDocument for Change {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line(Key::Change.get_key(), self.change.get_label().as_bytes())?;
+        for (key, value) in [(Key::Kind, &self.kind), (Key::Name, &self.name), (Key::Usr, &self.usr), (Key::Parent, &self.parent), 
+            (Key::Location, &self.location)] {
+            json.render_line(key.get_key(), value.as_bytes())?;
+        }
+        if let Some((old, new)) = &self.values {
+            json.render_line(Key::Old.get_key(), old.as_bytes())?;
+            json.render_line(Key::New.get_key(), new.as_bytes())?;
+        }
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+impl Change {
+    fn new(change: ChangeKind, declaration: &Declaration, values: Option<(String, String)>) -> Change {
+        Change {
+            change,
+            kind: declaration.kind.clone(),
+            name: declaration.name.clone(),
+            usr: declaration.usr.clone(),
+            parent: declaration.parent_name.clone(),
+            location: declaration.location.clone(),
+            values,
+        }
+    }
+}
+
+// changes between two declarations taken as the same one
+fn compare(old: &Declaration, new: &Declaration, changes: &mut Vec<Change>) {
+    let (old_signature, new_signature) = (old.get_signature(), new.get_signature());
+    if old_signature != new_signature {
+        let change = match is_function(&new.kind) {
+            true => ChangeKind::SignatureChanged,
+            false => ChangeKind::TypeChanged,
+        };
+        changes.push(Change::new(change, new, Some((old_signature, new_signature))));
+    }
+    if old.get_file() != new.get_file() {
+        changes.push(Change::new(ChangeKind::Moved, new, Some((String::from(old.get_file()), String::from(new.get_file())))));
+    }
+}
+
+/// Declarations of two revisions taken as the same one, and the ones only in the old or the new revision.
+pub(crate) struct Matching<'a, D> {
+    pub(crate) matched: Vec<(&'a D, &'a D)>,
+    pub(crate) removed: Vec<&'a D>,
+    pub(crate) added: Vec<&'a D>,
+}
+
+/// Matches the declarations of two revisions by key, usually the USR, first. The remaining ones are matched 
+/// by their structural key if exactly one old and one new declaration have it, so a declaration whose USR changed, 
+/// like an overload with other parameters, is still matched.
+pub(crate) fn match_declarations<'a, D, K: Eq + Hash>(old: &'a BTreeMap<String, D>, new: &'a BTreeMap<String, D>, 
+    get_structural_key: impl Fn(&'a D) -> K) -> Matching<'a, D> {
+    let mut matching = Matching { matched: Vec::new(), removed: Vec::new(), added: Vec::new() };
+    // removed and added declarations by structural key
+    let mut unmatched: HashMap<K, (Vec<&D>, Vec<&D>)> = HashMap::new();
+    for (key, declaration) in old {
+        match new.get(key) {
+            Some(new_declaration) => matching.matched.push((declaration, new_declaration)),
+            None => unmatched.entry(get_structural_key(declaration)).or_default().0.push(declaration),
+        }
+    }
+    for (key, declaration) in new {
+        if !old.contains_key(key) {
+            unmatched.entry(get_structural_key(declaration)).or_default().1.push(declaration);
+        }
+    }
+    for (removed, added) in unmatched.into_values() {
+        match (removed.as_slice(), added.as_slice()) {
+            ([old_declaration], [new_declaration]) => matching.matched.push((old_declaration, new_declaration)),
+            _ => {
+                matching.removed.extend(removed);
+                matching.added.extend(added);
+            },
+        }
+    }
+    matching
+}
+
+/// Changes between the declarations of two revisions, ordered by name. 
+/// Declarations are matched by USR first, the remaining ones by parent, kind and name if that is unambiguous.
+pub fn diff_declarations(old: &Declarations, new: &Declarations) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let matching = match_declarations(&old.declarations, &new.declarations, Declaration::get_structural_key);
+    for (old_declaration, new_declaration) in matching.matched {
+        compare(old_declaration, new_declaration, &mut changes);
+    }
+    changes.extend(matching.removed.into_iter().map(|declaration| Change::new(ChangeKind::Removed, declaration, None)));
+    changes.extend(matching.added.into_iter().map(|declaration| Change::new(ChangeKind::Added, declaration, None)));
+    // members moved along with their parent are not reported on their own
+    let moves = changes.iter()
+    .filter(|change| change.change == ChangeKind::Moved)
+    .map(|change| (change.name.clone(), change.values.clone()))
+    .collect::<HashSet<_>>();
+    changes.retain(|change| change.change != ChangeKind::Moved || !moves.contains(&(change.parent.clone(), change.values.clone())));
+    changes.sort_by(|left, right| (&left.name, left.change, &left.usr).cmp(&(&right.name, right.change, &right.usr)));
+    changes
+}
+
+/// Semantic changes between two AST files, or two output directories.
+#[derive(Clone, Debug, Default)]
+pub struct TreeDiff {
+    pub old: String,
+    pub new: String,
+    /// Inputs present in only one of the output directories, relative to their source roots.
+    pub added_files: Vec<String>,
+    pub removed_files: Vec<String>,
+    pub changes: Vec<Change>,
+}
+
+impl WriteDocument for TreeDiff {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line(Key::Old.get_key(), self.old.as_bytes())?;
+        json.render_line(Key::New.get_key(), self.new.as_bytes())?;
+        json.render_line_without_value(Key::Files.get_key())?;
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        Array::new(Key::Added, self.added_files.clone()).serialize(json)?;
+        Array::new(Key::Removed, self.removed_files.clone()).serialize(json)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)?;
+        Array::new(Key::Changes, self.changes.clone()).serialize(json)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+impl TreeDiff {
+    /// One line per change and file, followed by the counts.
+    pub fn write_summary<W: Write>(&self, out: &mut W) -> Result<()> {
+        for file in &self.removed_files {
+            writeln!(out, "- file {}", file)?;
+        }
+        for file in &self.added_files {
+            writeln!(out, "+ file {}", file)?;
+        }
+        let mut counts = BTreeMap::new();
+        for change in &self.changes {
+            *counts.entry(change.change).or_insert(0) += 1;
+            match (change.change, &change.values) {
+                (ChangeKind::Removed, _) => writeln!(out, "- {} {} at {}", change.kind, change.name, change.location)?,
+                (ChangeKind::Added, _) => writeln!(out, "+ {} {} at {}", change.kind, change.name, change.location)?,
+                (ChangeKind::Moved, Some((old, new))) => writeln!(out, "> {} {}: {} -> {}", change.kind, change.name, old, new)?,
+                (_, Some((old, new))) => writeln!(out, "~ {} {}: {} -> {}", change.kind, change.name, old, new)?,
+                (_, None) => writeln!(out, "~ {} {}", change.kind, change.name)?,
+            }
+        }
+        let count = |kinds: &[ChangeKind]| kinds.iter().map(|kind| counts.get(kind).copied().unwrap_or(0)).sum::<usize>();
+        writeln!(out, "{} added, {} removed, {} changed, {} moved", count(&[ChangeKind::Added]), count(&[ChangeKind::Removed]), 
+            count(&[ChangeKind::SignatureChanged, ChangeKind::TypeChanged]), count(&[ChangeKind::Moved]))
+    }
+}
+
+/// Compares two trees, locations are taken relative to the directory of each translation unit.
+pub fn diff_trees(old: &Tree, new: &Tree) -> Vec<Change> {
+    let collect = |tree: &Tree| {
+        let mut declarations = Declarations::default();
+        declarations.add_tree(tree, &PathBuf::from(common_root(std::slice::from_ref(&tree.node.name))));
+        declarations
+    };
+    diff_declarations(&collect(old), &collect(new))
+}
+
+// declarations of all files listed in the index of the output directory, read one at a time
+fn collect_directory(output_dir: &str) -> std::result::Result<(Declarations, BTreeSet<String>), Error> {
+    let files = read_output_index(output_dir)?;
+    let root = PathBuf::from(common_root(&files.iter().map(|(input, _)| input.clone()).collect::<Vec<String>>()));
+    let mut declarations = Declarations::default();
+    let mut inputs = BTreeSet::new();
+    for (input, output) in files {
+        let tree = read_tree_file(&PathBuf::from(output_dir).join(output).to_string_lossy())?;
+        declarations.add_tree(&tree, &root);
+        let input = normalize_path(&input);
+        inputs.insert(input.strip_prefix(&root).unwrap_or(&input).to_string_lossy().replace('\\', "/"));
+    }
+    Ok((declarations, inputs))
+}
+
+/// Compares two JSON AST files, or two output directories with their `index.json`. 
+/// Directories are compared as a whole, a declaration moved between files is reported as moved.
+pub fn diff_paths(old: &str, new: &str) -> std::result::Result<TreeDiff, Error> {
+    let mut diff = TreeDiff { old: String::from(old), new: String::from(new), ..TreeDiff::default() };
+    if Path::new(old).is_dir() && Path::new(new).is_dir() {
+        let (old_declarations, old_inputs) = collect_directory(old)?;
+        let (new_declarations, new_inputs) = collect_directory(new)?;
+        diff.removed_files = old_inputs.difference(&new_inputs).cloned().collect();
+        diff.added_files = new_inputs.difference(&old_inputs).cloned().collect();
+        diff.changes = diff_declarations(&old_declarations, &new_declarations);
+    } else {
+        diff.changes = diff_trees(&read_tree_file(old)?, &read_tree_file(new)?);
+    }
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::tests::node;
+    use crate::graph::TypeInfo;
+
+    fn declaration(kind: &str, name: &str, usr: &str, type_name: Option<&str>, location: &str, children: Vec<Tree>) -> Tree {
+        let mut node = node(0);
+        node.kind = String::from(kind);
+        node.name = String::from(name);
+        node.usr = String::from(usr);
+        node.location = String::from(location);
+        node.type_ = type_name.map(|display_name| TypeInfo {
+            display_name: String::from(display_name),
+            canonical: String::from(display_name),
+            kind: String::from("Unexposed"),
+            pointee: None,
+            element: None,
+            result: None,
+        });
+        Tree { node, children }
+    }
+
+    fn collect(children: Vec<Tree>) -> Declarations {
+        let mut declarations = Declarations::default();
+        declarations.add_tree(&declaration("TranslationUnit", "/src/a.cpp", "", None, "", children), Path::new("/src"));
+        declarations
+    }
+
+    fn function(usr: &str, type_name: &str, location: &str, body: Vec<Tree>) -> Tree {
+        declaration("FunctionDecl", "f", usr, Some(type_name), location, vec![declaration("CompoundStmt", "", "", None, location, body)])
+    }
+
+    fn summary(changes: &[Change]) -> Vec<String> {
+        changes.iter().map(|change| match &change.values {
+            Some((old, new)) => format!("{} {}: {} -> {}", change.change.get_label(), change.name, old, new),
+            None => format!("{} {}", change.change.get_label(), change.name),
+        }).collect()
+    }
+
+    #[test]
+    fn changed_signature_is_matched_by_name_when_the_usr_changed() {
+        let old = collect(vec![function("c:@F@f#I#", "void (int)", "/src/a.cpp:1:6", vec![])]);
+        let new = collect(vec![function("c:@F@f#l#", "void (long)", "/src/a.cpp:1:6", vec![])]);
+        assert_eq!(summary(&diff_declarations(&old, &new)), ["signatureChanged f: void (int) -> void (long)"]);
+    }
+
+    #[test]
+    fn local_declarations_are_not_compared() {
+        let local = |type_name| declaration("DeclStmt", "", "", None, "/src/a.cpp:2:5", 
+            vec![declaration("VarDecl", "count", "c:a.cpp@20@F@f#@count", Some(type_name), "/src/a.cpp:2:9", vec![])]);
+        let old = collect(vec![function("c:@F@f#", "void ()", "/src/a.cpp:1:6", vec![local("int")])]);
+        let new = collect(vec![function("c:@F@f#", "void ()", "/src/a.cpp:1:6", vec![local("long")])]);
+        assert!(diff_declarations(&old, &new).is_empty());
+        assert!(diff_declarations(&old, &collect(vec![function("c:@F@f#", "void ()", "/src/a.cpp:1:6", vec![])])).is_empty());
+    }
+
+    #[test]
+    fn members_moved_with_their_parent_are_reported_once() {
+        let class = |location: &str| declaration("ClassDecl", "C", "c:@S@C", None, location, 
+            vec![declaration("FieldDecl", "x", "c:@S@C@FI@x", Some("int"), location, vec![])]);
+        let old = collect(vec![class("/src/a.h:1:7"), declaration("VarDecl", "removed", "c:@removed", Some("int"), "/src/a.h:5:5", vec![])]);
+        let new = collect(vec![class("/src/b.h:1:7"), declaration("VarDecl", "added", "c:@added", Some("int"), "/src/b.h:5:5", vec![])]);
+        assert_eq!(summary(&diff_declarations(&old, &new)), ["moved C: a.h -> b.h", "added added", "removed removed"]);
+    }
+}
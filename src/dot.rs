@@ -0,0 +1,46 @@
+/* 
+   Copyright (c) 2022 ArSysOp.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+  
+       http:  www.apache.org/licenses/LICENSE-2.0
+  
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+  
+   SPDX-License-Identifier: Apache-2.0
+  
+   Contributors:
+     ArSysOp - initial API and implementation
+*/
+
+use std::io::{Result, Write};
+
+pub fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+pub fn render_header<W: Write>(out: &mut W, name: &str) -> Result<()> {
+    writeln!(out, "digraph \"{}\" {{", escape(name))
+}
+
+pub fn render_node<W: Write>(out: &mut W, id: &str, label: &str, attributes: &str) -> Result<()> {
+    writeln!(out, "  \"{}\" [label=\"{}\"{}];", escape(id), escape(label), attributes)
+}
+
+pub fn render_edge<W: Write>(out: &mut W, from: &str, to: &str, attributes: &str) -> Result<()> {
+    if attributes.is_empty() {
+        writeln!(out, "  \"{}\" -> \"{}\";", escape(from), escape(to))
+    } else {
+        writeln!(out, "  \"{}\" -> \"{}\" [{}];", escape(from), escape(to), attributes)
+    }
+}
+
+pub fn render_footer<W: Write>(out: &mut W) -> Result<()> {
+    writeln!(out, "}}")
+}
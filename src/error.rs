@@ -0,0 +1,92 @@
+/* 
+   Copyright (c) 2022 ArSysOp.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+  
+       http:  www.apache.org/licenses/LICENSE-2.0
+  
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+  
+   SPDX-License-Identifier: Apache-2.0
+  
+   Contributors:
+     ArSysOp - initial API and implementation
+*/
+
+use std::{fmt, io};
+use clang::SourceError;
+
+/// Exit code of the tool when a file could not be parsed.
+pub const EXIT_PARSE_FAILED: i32 = 1;
+/// Exit code of the tool when the options or the compilation database are invalid.
+pub const EXIT_INVALID_CONFIG: i32 = 2;
+/// Exit code of the tool when an input could not be read or an output written.
+pub const EXIT_IO_FAILED: i32 = 3;
+/// Not an error: `api-diff` found changes breaking source or binary compatibility.
+pub const EXIT_BREAKING_CHANGES: i32 = 4;
+
+/// Failure of the parsing pipeline, of reading an input or of writing an output.
+#[derive(Debug)]
+pub enum Error {
+    /// libclang could not be loaded, or is already in use in this process.
+    Clang(String),
+    /// libclang could not produce a translation unit for the file.
+    Parse(String, SourceError),
+    /// Reading an input or writing an output failed.
+    Io(String, io::Error),
+    /// The compilation database in the directory could not be loaded.
+    CompilationDatabase(String),
+    /// A glob pattern passed on the command line is malformed.
+    Pattern(String, glob::PatternError),
+    /// A command line option has a value outside of its allowed set.
+    InvalidOption(String, String),
+    /// Writing into the SQLite database failed.
+    Database(String, rusqlite::Error),
+    /// An output file read back, like an AST file of another format version, is malformed.
+    Dump(String, serde_json::Error),
+}
+
+impl Error {
+    /// Exit code of the tool for this failure.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Clang(..) | Error::Parse(..) | Error::Dump(..) => EXIT_PARSE_FAILED,
+            Error::Io(..) | Error::Database(..) => EXIT_IO_FAILED,
+            Error::CompilationDatabase(..) | Error::Pattern(..) | Error::InvalidOption(..) => EXIT_INVALID_CONFIG,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Clang(err) => write!(f, "failed to initialize libclang: {}", err),
+            Error::Parse(path, err) => write!(f, "failed to parse {}: {}", path, err),
+            Error::Io(path, err) => write!(f, "{}: {}", path, err),
+            Error::CompilationDatabase(dir) => write!(f, "failed to load compilation database from {}", dir),
+            Error::Pattern(pattern, err) => write!(f, "invalid pattern {}: {}", pattern, err),
+            Error::InvalidOption(option, value) => write!(f, "invalid value {} for --{}", value, option),
+            Error::Database(path, err) => write!(f, "{}: {}", path, err),
+            Error::Dump(path, err) => write!(f, "failed to read {}: {}", path, err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Parse(_, err) => Some(err),
+            Error::Io(_, err) => Some(err),
+            Error::Clang(_) | Error::CompilationDatabase(_) | Error::InvalidOption(..) => None,
+            Error::Pattern(_, err) => Some(err),
+            Error::Database(_, err) => Some(err),
+            Error::Dump(_, err) => Some(err),
+        }
+    }
+}
@@ -52,6 +52,12 @@ impl Name for Usr {
     }
 }
 
+impl Name for source::File<'_> {
+    fn get_name(&self) -> String {
+        self.get_path().to_string_lossy().to_string()
+    }
+}
+
 impl Name for source::SourceLocation<'_> {
     fn get_name(&self) -> String {
         let location = self.get_presumed_location();
@@ -59,6 +65,23 @@ impl Name for source::SourceLocation<'_> {
     }
 }
 
+/// The name prefixed with the names of its enclosing namespaces and records, like `ns::Class::method`.
+pub fn get_qualified_name(entity: Entity) -> String {
+    let mut names = vec![return_empty_if_null(entity.get_name())];
+    let mut parent = entity.get_semantic_parent();
+    while let Some(scope) = parent.filter(|scope| scope.get_kind() != EntityKind::TranslationUnit) {
+        names.push(scope.get_name().unwrap_or_else(|| String::from("(anonymous)")));
+        parent = scope.get_semantic_parent();
+    }
+    names.reverse();
+    names.join("::")
+}
+
+/// USR of the entity, which identifies it across translation units, unless it has none.
+pub fn get_usr(entity: Entity) -> Option<String> {
+    entity.get_usr().map(|usr| usr.0).filter(|usr| !usr.is_empty())
+}
+
 pub fn return_empty_if_null(name: Option<String>) -> String {
     match name {
         Some(name) => name,
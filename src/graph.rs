@@ -19,159 +19,1105 @@
      ArSysOp - initial API and implementation
 */
 
-use std::collections::HashSet;
-use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
-use std::hash::Hasher;
-use crate::json::JSONSerializer;
+use std::fmt;
+use std::io::{Result, Write};
+use serde::ser::SerializeSeq;
+use std::path::PathBuf;
+use crate::json::{Cell, DocumentSerializer, TableWriter};
 use crate::json::Bracket;
-use crate::kind::get_kind_label;
+use crate::kind::{get_kind_label, get_type_kind_label};
 use clang::*;
 use crate::get_name::*;
+use crate::diagnostics::DiagnosticInfo;
+use crate::output::normalize_path;
 
-pub trait Serialize {
-    fn serialize(&self, json: JSONSerializer) -> JSONSerializer;
+/// Values written with a `DocumentSerializer`, in any of its encodings.
+pub trait WriteDocument {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()>;
+}
+
+impl WriteDocument for String {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_value(self.as_bytes())
+    }
 }
 
 #[derive(Clone, Debug)]
-pub struct Array<T: Serialize> {
+pub struct Array<T: WriteDocument> {
     name: String,
     members: Vec<T>
 }
 
-impl<T: Serialize> Serialize for Array<T> {
-    fn serialize(&self, mut json: JSONSerializer) -> JSONSerializer {
-        json.render_line_without_value(self.name.as_bytes());
-        json.render_bracket(Bracket::LBrace);
-        json.prefix.expand();
-        let mut first = true;
-        for member in &self.members {
-            if first {
-                first = false;
-            } else {
-                json.render_comma();
-            }
-            json = member.serialize(json);
+impl<T: WriteDocument> Array<T> {
+    pub fn new(key: Key, members: Vec<T>) -> Array<T> {
+        Array {
+            name: String::from_utf8(key.get_key().to_vec()).expect("failed to make string from &[u8]"),
+            members
         }
-        json.prefix.shrink();
-        json.render_bracket(Bracket::RBrace);
-        json
     }
 }
 
+impl<T: WriteDocument> WriteDocument for Array<T> {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        render_members(self.name.as_bytes(), &self.members, json)
+    }
+}
+
+fn render_members<T: WriteDocument, W: Write>(name: &[u8], members: &[T], json: &mut DocumentSerializer<W>) -> Result<()> {
+    json.render_line_without_value(name)?;
+    json.render_bracket(Bracket::LBrace)?;
+    json.prefix.expand();
+    for member in members {
+        member.serialize(json)?;
+    }
+    json.prefix.shrink();
+    json.render_bracket(Bracket::RBrace)
+}
+
+/// Writes the members as an array under the key, like `Array` does without taking them over.
+pub fn serialize_array<T: WriteDocument, W: Write>(key: Key, members: &[T], json: &mut DocumentSerializer<W>) -> Result<()> {
+    render_members(key.get_key(), members, json)
+}
+
 #[derive(Clone, Debug)]
-enum Key {
+pub enum Key {
     Kind,
     Name,
     DisplayName,
     Type,
+    Canonical,
+    Pointee,
+    Element,
+    Result,
     Usr,
     Location,
+    Range,
+    Begin,
+    End,
+    Spelling,
+    Expansion,
+    Presumed,
+    File,
+    Line,
+    Column,
+    Offset,
+    Diagnostics,
+    Severity,
+    Message,
+    Category,
+    Option,
+    Ranges,
+    FixIts,
+    Text,
+    Notes,
+    Files,
+    Input,
+    Output,
+    Symbols,
+    Definition,
+    Declarations,
+    ReferencedBy,
+    Functions,
+    Calls,
+    Caller,
+    Callee,
+    Dispatch,
+    Candidates,
+    CallSites,
+    Classes,
+    Bases,
+    Derived,
+    Methods,
+    Access,
+    Virtual,
+    Pure,
+    Final,
+    Abstract,
+    Overrides,
+    Id,
+    Referenced,
+    SemanticParent,
+    LexicalParent,
+    ParentId,
+    ChildIndex,
+    Source,
+    Target,
+    BeginLine,
+    EndLine,
+    Truncated,
+    Includes,
+    Includer,
+    System,
+    Locations,
+    Headers,
+    IncludedBy,
+    Transitive,
     Children,
+    FormatVersion,
+    Change,
+    Changes,
+    Parent,
+    Old,
+    New,
+    Added,
+    Removed,
+    Signature,
+    Parameters,
+    Variadic,
+    Const,
+    Static,
+    Size,
+    Alignment,
+    Fields,
+    VirtualMethods,
+    Underlying,
+    Constants,
+    Value,
+    SourceBreaking,
+    AbiBreaking,
 }
 
 impl Key {
-    fn get_key(&self) -> &[u8] {
+    pub fn get_key(&self) -> &'static [u8] {
         match self {
             Key::Kind => b"kind",
             Key::Name => b"name",
             Key::DisplayName => b"displayName",
             Key::Type => b"type",
+            Key::Canonical => b"canonical",
+            Key::Pointee => b"pointee",
+            Key::Element => b"element",
+            Key::Result => b"result",
             Key::Usr => b"usr",
             Key::Location => b"location",
+            Key::Range => b"range",
+            Key::Begin => b"begin",
+            Key::End => b"end",
+            Key::Spelling => b"spelling",
+            Key::Expansion => b"expansion",
+            Key::Presumed => b"presumed",
+            Key::File => b"file",
+            Key::Line => b"line",
+            Key::Column => b"column",
+            Key::Offset => b"offset",
+            Key::Diagnostics => b"diagnostics",
+            Key::Severity => b"severity",
+            Key::Message => b"message",
+            Key::Category => b"category",
+            Key::Option => b"option",
+            Key::Ranges => b"ranges",
+            Key::FixIts => b"fixIts",
+            Key::Text => b"text",
+            Key::Notes => b"notes",
+            Key::Files => b"files",
+            Key::Input => b"input",
+            Key::Output => b"output",
+            Key::Symbols => b"symbols",
+            Key::Definition => b"definition",
+            Key::Declarations => b"declarations",
+            Key::ReferencedBy => b"referencedBy",
+            Key::Functions => b"functions",
+            Key::Calls => b"calls",
+            Key::Caller => b"caller",
+            Key::Callee => b"callee",
+            Key::Dispatch => b"dispatch",
+            Key::Candidates => b"candidates",
+            Key::CallSites => b"callSites",
+            Key::Classes => b"classes",
+            Key::Bases => b"bases",
+            Key::Derived => b"derived",
+            Key::Methods => b"methods",
+            Key::Access => b"access",
+            Key::Virtual => b"virtual",
+            Key::Pure => b"pure",
+            Key::Final => b"final",
+            Key::Abstract => b"abstract",
+            Key::Overrides => b"overrides",
+            Key::Id => b"id",
+            Key::Referenced => b"referenced",
+            Key::SemanticParent => b"semanticParent",
+            Key::LexicalParent => b"lexicalParent",
+            Key::ParentId => b"parentId",
+            Key::ChildIndex => b"childIndex",
+            Key::Source => b"source",
+            Key::Target => b"target",
+            Key::BeginLine => b"beginLine",
+            Key::EndLine => b"endLine",
+            Key::Truncated => b"truncated",
+            Key::Includes => b"includes",
+            Key::Includer => b"includer",
+            Key::System => b"system",
+            Key::Locations => b"locations",
+            Key::Headers => b"headers",
+            Key::IncludedBy => b"includedBy",
+            Key::Transitive => b"transitive",
             Key::Children => b"children",
+            Key::FormatVersion => b"formatVersion",
+            Key::Change => b"change",
+            Key::Changes => b"changes",
+            Key::Parent => b"parent",
+            Key::Old => b"old",
+            Key::New => b"new",
+            Key::Added => b"added",
+            Key::Removed => b"removed",
+            Key::Signature => b"signature",
+            Key::Parameters => b"parameters",
+            Key::Variadic => b"variadic",
+            Key::Const => b"const",
+            Key::Static => b"static",
+            Key::Size => b"size",
+            Key::Alignment => b"alignment",
+            Key::Fields => b"fields",
+            Key::VirtualMethods => b"virtualMethods",
+            Key::Underlying => b"underlying",
+            Key::Constants => b"constants",
+            Key::Value => b"value",
+            Key::SourceBreaking => b"sourceBreaking",
+            Key::AbiBreaking => b"abiBreaking",
         }
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct Node {
-    kind: (Key, String),
-    name: (Key, String),
-    display_name: (Key, String),
-    _type: (Key, String),
-    usr: (Key, String),
-    location: (Key, String),
-    children: Array<Node>
-}
-
-impl Serialize for Node {
-    fn serialize(&self, mut json: JSONSerializer) -> JSONSerializer {
-        json.render_bracket(Bracket::LCurly);
+/// Type of a node, with the types it points to, contains or returns.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeInfo {
+    pub display_name: String,
+    pub canonical: String,
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pointee: Option<Box<TypeInfo>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub element: Option<Box<TypeInfo>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Box<TypeInfo>>,
+}
+
+impl WriteDocument for TypeInfo {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line(Key::DisplayName.get_key(), self.display_name.as_bytes())?;
+        json.render_line(Key::Canonical.get_key(), self.canonical.as_bytes())?;
+        json.render_line(Key::Kind.get_key(), self.kind.as_bytes())?;
+        for (key, nested) in [(Key::Pointee, &self.pointee), (Key::Element, &self.element), (Key::Result, &self.result)] {
+            if let Some(type_info) = nested {
+                json.render_line_without_value(key.get_key())?;
+                type_info.serialize(json)?;
+            }
+        }
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+impl TypeInfo {
+    pub fn new(type_: Type) -> TypeInfo {
+        let nested = |type_: Option<Type>| type_.map(|type_| Box::new(TypeInfo::new(type_)));
+        TypeInfo {
+            display_name: type_.get_name(),
+            canonical: type_.get_canonical_type().get_name(),
+            kind: get_type_kind_label(type_.get_kind()),
+            pointee: nested(type_.get_pointee_type()),
+            element: nested(type_.get_element_type()),
+            result: nested(type_.get_result_type()),
+        }
+    }
+}
+
+/// File, line, column and byte offset of one end of a source range. 
+/// libclang does not report byte offsets for presumed locations, so they have none.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Position {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u32>,
+}
+
+impl WriteDocument for Position {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line(Key::File.get_key(), self.file.as_bytes())?;
+        json.render_number_line(Key::Line.get_key(), self.line as u64)?;
+        json.render_number_line(Key::Column.get_key(), self.column as u64)?;
+        if let Some(offset) = self.offset {
+            json.render_number_line(Key::Offset.get_key(), offset as u64)?;
+        }
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+impl Position {
+    fn new(location: source::Location) -> Position {
+        Position {
+            file: location.file.get_name(),
+            line: location.line,
+            column: location.column,
+            offset: Some(location.offset),
+        }
+    }
+
+    fn new_presumed(location: (String, u32, u32)) -> Position {
+        Position {
+            file: location.0,
+            line: location.1,
+            column: location.2,
+            offset: None,
+        }
+    }
+}
+
+/// One end of a source range as spelled, after macro expansion and as presumed with `#line` directives.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RangeEnd {
+    pub spelling: Position,
+    pub expansion: Position,
+    pub presumed: Position,
+}
+
+impl WriteDocument for RangeEnd {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        for (key, position) in [(Key::Spelling, &self.spelling), (Key::Expansion, &self.expansion), (Key::Presumed, &self.presumed)] {
+            json.render_line_without_value(key.get_key())?;
+            position.serialize(json)?;
+        }
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+impl RangeEnd {
+    fn new(location: source::SourceLocation) -> RangeEnd {
+        RangeEnd {
+            spelling: Position::new(location.get_spelling_location()),
+            expansion: Position::new(location.get_expansion_location()),
+            presumed: Position::new_presumed(location.get_presumed_location()),
+        }
+    }
+}
+
+/// Source range of a node.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Range {
+    pub begin: RangeEnd,
+    pub end: RangeEnd,
+}
+
+impl WriteDocument for Range {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
         json.prefix.expand();
-        for attr in [&self.kind, &self.name, &self.display_name, /* &mut self._type, */ &self.usr, &self.location] {
-            json.render_line(attr.0.get_key(), attr.1.as_bytes());
+        for (key, end) in [(Key::Begin, &self.begin), (Key::End, &self.end)] {
+            json.render_line_without_value(key.get_key())?;
+            end.serialize(json)?;
         }
-        json = self.children.serialize(json);
         json.prefix.shrink();
-        json.render_bracket(Bracket::RCurly);
-        json
+        json.render_bracket(Bracket::RCurly)
     }
 }
 
+impl Range {
+    pub fn new(range: source::SourceRange) -> Range {
+        Range {
+            begin: RangeEnd::new(range.get_start()),
+            end: RangeEnd::new(range.get_end()),
+        }
+    }
+}
+
+/// Reference from a node to another entity: its ID if the entity is written to the same file, before or after the node, 
+/// its USR otherwise.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Link {
+    Id(u64),
+    Usr(String),
+}
+
+impl WriteDocument for Link {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        match self {
+            Link::Id(id) => json.render_number_line(Key::Id.get_key(), *id)?,
+            Link::Usr(usr) => json.render_line(Key::Usr.get_key(), usr.as_bytes())?,
+        }
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+impl Link {
+    fn new(target: Option<Entity>, ids: &HashMap<Entity, u64>) -> Option<Link> {
+        let target = target?;
+        match ids.get(&target) {
+            Some(id) => Some(Link::Id(*id)),
+            None => target.get_usr().map(|usr| Link::Usr(usr.get_name())),
+        }
+    }
+}
+
+/// Attributes of one AST node, without its children. 
+/// Nodes are handed to a `NodeWriter` one by one while the AST is traversed. 
+/// A node owns all its data, it outlives the translation unit and can be sent to other threads. 
+/// With serde, its fields are named as in the JSON files.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Node {
+    /// Position of the node in a pre-order traversal of the file, the root is 0.
+    pub id: u64,
+    pub kind: String,
+    pub name: String,
+    pub display_name: String,
+    /// Empty for entities without USR.
+    pub usr: String,
+    /// Presumed location as `file:line:column`.
+    pub location: String,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub type_: Option<TypeInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<Range>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub referenced: Option<Link>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub definition: Option<Link>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canonical: Option<Link>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub semantic_parent: Option<Link>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lexical_parent: Option<Link>,
+    /// Set when the children were left out because of the depth limit.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// Only set on the root node of a translation unit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostics: Option<Vec<DiagnosticInfo>>,
+}
+
 impl Node {
-    pub fn new<'a>(node: Entity<'a>, mut ast: &'a mut AST<'a>) -> (Node, &'a mut AST<'a>) {
-        let children_as_entities: Vec<Entity> = node.get_children().into_iter().collect::<Vec<_>>();
-        let mut children_as_nodes: Vec<Node> = Vec::new();
-        for child in children_as_entities {
-            if ast.nodes.contains(&child) {
-                let child_as_node_wuth_ast = Node::new(ast.nodes.take(&child).unwrap(), ast);
-                ast = child_as_node_wuth_ast.1;
-                children_as_nodes.push(child_as_node_wuth_ast.0); 
+    fn new(node: Entity, id: u64, ids: &HashMap<Entity, u64>) -> Node {
+        Node { 
+            id,
+            kind: get_kind_label(node.get_kind()), 
+            name: return_empty_if_null(node.get_name()), 
+            display_name: return_empty_if_null(node.get_display_name()),
+            usr: node.get_usr().get_name(),
+            location: node.get_location().get_name(), 
+            type_: node.get_type().map(TypeInfo::new),
+            range: node.get_range().map(Range::new),
+            referenced: Link::new(node.get_reference(), ids),
+            definition: Link::new(node.get_definition(), ids),
+            // most entities are their own canonical entity
+            canonical: Link::new(Some(node.get_canonical_entity()).filter(|canonical| *canonical != node), ids),
+            semantic_parent: Link::new(node.get_semantic_parent(), ids),
+            lexical_parent: Link::new(node.get_lexical_parent(), ids),
+            truncated: false,
+            diagnostics: None,
+        }
+    }
+
+    /// The links of the node by key, in the order they are written.
+    pub(crate) fn get_links(&self) -> [(Key, Option<&Link>); 5] {
+        [
+            (Key::Referenced, self.referenced.as_ref()),
+            (Key::Definition, self.definition.as_ref()),
+            (Key::Canonical, self.canonical.as_ref()),
+            (Key::SemanticParent, self.semantic_parent.as_ref()),
+            (Key::LexicalParent, self.lexical_parent.as_ref()),
+        ]
+    }
+
+    pub fn get_id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn get_kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    /// Empty for entities without USR.
+    pub fn get_usr(&self) -> &str {
+        &self.usr
+    }
+
+    /// Presumed location as `file:line:column`.
+    pub fn get_location(&self) -> &str {
+        &self.location
+    }
+
+    pub fn get_type_name(&self) -> Option<&str> {
+        self.type_.as_ref().map(|type_info| type_info.display_name.as_str())
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+/// Owned AST of a translation unit, independent of libclang once built. 
+/// With serde, the attributes of the node and its `children` are fields of the same object, as in the JSON files, 
+/// and the root carries the `formatVersion`, so `reader::read_tree` accepts the output. 
+/// `reader` deserializes it without buffering the children. 
+/// Cloning, comparing, formatting and dropping don't recurse, serializing grows the stack on demand, 
+/// so the depth of a tree is only limited by memory.
+pub struct Tree {
+    pub node: Node,
+    pub children: Vec<Tree>,
+}
+
+impl Clone for Tree {
+    fn clone(&self) -> Tree {
+        let mut builder = TreeBuilder::default();
+        self.write(&mut builder).expect("building a tree does not fail");
+        builder.into_tree().expect("a tree has a root")
+    }
+}
+
+// the nodes in pre-order with their depth determine the tree
+impl PartialEq for Tree {
+    fn eq(&self, other: &Tree) -> bool {
+        self.iter().map(|(tree, depth)| (&tree.node, depth)).eq(other.iter().map(|(tree, depth)| (&tree.node, depth)))
+    }
+}
+
+impl Eq for Tree {}
+
+impl fmt::Debug for Tree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter().map(|(tree, depth)| (depth, &tree.node))).finish()
+    }
+}
+
+// serde serializers take one nested call per level, the stack is extended on the heap when it runs low
+const SERIALIZE_RED_ZONE: usize = 64 * 1024;
+const SERIALIZE_STACK_SIZE: usize = 2 * 1024 * 1024;
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SerializedTree<'a> {
+    // only on the root, as `reader` expects
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format_version: Option<u64>,
+    #[serde(flatten)]
+    node: &'a Node,
+    children: SerializedChildren<'a>,
+}
+
+impl SerializedTree<'_> {
+    fn new(tree: &Tree) -> SerializedTree<'_> {
+        SerializedTree { format_version: None, node: &tree.node, children: SerializedChildren(&tree.children) }
+    }
+}
+
+struct SerializedChildren<'a>(&'a [Tree]);
+
+impl serde::Serialize for SerializedChildren<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut children = serializer.serialize_seq(Some(self.0.len()))?;
+        for child in self.0 {
+            stacker::maybe_grow(SERIALIZE_RED_ZONE, SERIALIZE_STACK_SIZE, || children.serialize_element(&SerializedTree::new(child)))?;
+        }
+        children.end()
+    }
+}
+
+impl serde::Serialize for Tree {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        SerializedTree { format_version: Some(FORMAT_VERSION), ..SerializedTree::new(self) }.serialize(serializer)
+    }
+}
+
+// trees are cached and handed between threads by library users
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Tree>()
+};
+
+impl WriteDocument for Tree {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        self.write(json)
+    }
+}
+
+impl Tree {
+    /// Hands the nodes of the tree to the writer in the order `AST::write` does.
+    pub fn write<N: NodeWriter>(&self, writer: &mut N) -> Result<()> {
+        writer.begin_node(&self.node, None, 0)?;
+        let mut stack = vec![(self, self.children.iter().enumerate())];
+        while let Some((tree, children)) = stack.last_mut() {
+            match children.next() {
+                Some((index, child)) => {
+                    writer.begin_node(&child.node, Some(tree.node.id), index as u64)?;
+                    stack.push((child, child.children.iter().enumerate()));
+                },
+                None => {
+                    writer.end_node(&tree.node)?;
+                    stack.pop();
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Iterates over the tree in pre-order, with the depth of every node, the root being at 0.
+    pub fn iter(&self) -> TreeIter<'_> {
+        TreeIter { pending: vec![(self, 0)] }
+    }
+
+    /// All nodes of the given kind, e.g. `"FunctionDecl"`, in pre-order.
+    pub fn find_by_kind<'a>(&'a self, kind: &'a str) -> impl Iterator<Item = &'a Tree> + 'a {
+        self.iter().map(|(tree, _)| tree).filter(move |tree| tree.node.kind == kind)
+    }
+}
+
+// children are dropped one by one, a recursive drop would overflow the stack on deep trees
+impl Drop for Tree {
+    fn drop(&mut self) {
+        let mut pending = std::mem::take(&mut self.children);
+        while let Some(mut tree) = pending.pop() {
+            pending.append(&mut tree.children);
+        }
+    }
+}
+
+/// Pre-order iterator over a tree, returned by `Tree::iter`.
+pub struct TreeIter<'a> {
+    pending: Vec<(&'a Tree, usize)>,
+}
+
+impl<'a> Iterator for TreeIter<'a> {
+    type Item = (&'a Tree, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (tree, depth) = self.pending.pop()?;
+        self.pending.extend(tree.children.iter().rev().map(|child| (child, depth + 1)));
+        Some((tree, depth))
+    }
+}
+
+/// Builds an owned `Tree` from the nodes it receives.
+#[derive(Default)]
+pub struct TreeBuilder {
+    // nodes begun but not ended yet, the root first
+    stack: Vec<Tree>,
+    root: Option<Tree>,
+}
+
+impl NodeWriter for TreeBuilder {
+    fn begin_node(&mut self, node: &Node, _parent_id: Option<u64>, _child_index: u64) -> Result<()> {
+        self.stack.push(Tree { node: node.clone(), children: Vec::new() });
+        Ok(())
+    }
+
+    fn end_node(&mut self, _node: &Node) -> Result<()> {
+        let tree = self.stack.pop().expect("unbalanced nodes");
+        match self.stack.last_mut() {
+            Some(parent) => parent.children.push(tree),
+            None => self.root = Some(tree),
+        }
+        Ok(())
+    }
+}
+
+impl TreeBuilder {
+    /// The tree once its root has ended, `None` if no node was written.
+    pub fn into_tree(self) -> Option<Tree> {
+        self.root
+    }
+}
+
+/// Version of the layout of the tree files, written as `formatVersion` into the root node. 
+/// Raised whenever existing readers could misread the files.
+pub const FORMAT_VERSION: u64 = 1;
+
+/// Receives the nodes of a translation unit in pre-order: every node begins before its children and ends after them.
+pub trait NodeWriter {
+    fn begin_node(&mut self, node: &Node, parent_id: Option<u64>, child_index: u64) -> Result<()>;
+    fn end_node(&mut self, node: &Node) -> Result<()>;
+}
+
+/// Nested tree, the children of every node are written into its `children` array.
+impl<W: Write> NodeWriter for DocumentSerializer<W> {
+    fn begin_node(&mut self, node: &Node, parent_id: Option<u64>, _child_index: u64) -> Result<()> {
+        self.render_bracket(Bracket::LCurly)?;
+        self.prefix.expand();
+        // first, so readers can reject an unknown layout before reading the tree
+        if parent_id.is_none() {
+            self.render_number_line(Key::FormatVersion.get_key(), FORMAT_VERSION)?;
+        }
+        self.render_number_line(Key::Id.get_key(), node.id)?;
+        for (key, value) in [(Key::Kind, &node.kind), (Key::Name, &node.name), (Key::DisplayName, &node.display_name), 
+            (Key::Usr, &node.usr), (Key::Location, &node.location)] {
+            self.render_line(key.get_key(), value.as_bytes())?;
+        }
+        if let Some(type_info) = &node.type_ {
+            self.render_line_without_value(Key::Type.get_key())?;
+            type_info.serialize(self)?;
+        }
+        if let Some(range) = &node.range {
+            self.render_line_without_value(Key::Range.get_key())?;
+            range.serialize(self)?;
+        }
+        for (key, link) in node.get_links() {
+            if let Some(target) = link {
+                self.render_line_without_value(key.get_key())?;
+                target.serialize(self)?;
             }
         }
-        (Node { 
-            kind: (Key::Kind, get_kind_label(node.get_kind())), 
-            name: (Key::Name, return_empty_if_null(node.get_name())), 
-            display_name: (Key::DisplayName, return_empty_if_null(node.get_display_name())),
-            _type: (Key::Type, node.get_type().get_name()),
-            usr: (Key::Usr, node.get_usr().get_name()),
-            location: (Key::Location, node.get_location().get_name()), 
-            children: (Array { 
-                name: (String::from_utf8(Key::Children.get_key().to_vec()).expect("failed to make string from &[u8]")), 
-                members: (children_as_nodes) 
-            }), 
-        },
-        ast)
+        if node.truncated {
+            self.render_bool_line(Key::Truncated.get_key(), true)?;
+        }
+        if let Some(diagnostics) = &node.diagnostics {
+            serialize_array(Key::Diagnostics, diagnostics, self)?;
+        }
+        self.render_line_without_value(Key::Children.get_key())?;
+        self.render_bracket(Bracket::LBrace)?;
+        self.prefix.expand();
+        Ok(())
+    }
+
+    fn end_node(&mut self, _node: &Node) -> Result<()> {
+        self.prefix.shrink();
+        self.render_bracket(Bracket::RBrace)?;
+        self.prefix.shrink();
+        self.render_bracket(Bracket::RCurly)
+    }
+}
+
+// lines of the range in the expansion location
+pub const NODE_COLUMNS: [Key; 12] = [Key::Id, Key::ParentId, Key::ChildIndex, Key::Kind, Key::Name, Key::DisplayName, 
+    Key::Usr, Key::Location, Key::Type, Key::BeginLine, Key::EndLine, Key::Truncated];
+// the target is either a node ID in the same file or a USR
+pub const EDGE_COLUMNS: [Key; 4] = [Key::Source, Key::Kind, Key::Target, Key::Usr];
+// one row per node with a type, identified by the node ID
+pub const TYPE_COLUMNS: [Key; 4] = [Key::Id, Key::DisplayName, Key::Canonical, Key::Kind];
+
+/// Flat tables: one row per node, one row per link of a node, one row per node with a type.
+pub struct TableNodeWriter<N: TableWriter, E: TableWriter, T: TableWriter> {
+    pub nodes: N,
+    pub edges: E,
+    pub types: T,
+}
+
+impl<N: TableWriter, E: TableWriter, T: TableWriter> NodeWriter for TableNodeWriter<N, E, T> {
+    fn begin_node(&mut self, node: &Node, parent_id: Option<u64>, child_index: u64) -> Result<()> {
+        let type_name = node.type_.as_ref().map(|type_info| type_info.display_name.as_bytes());
+        let line = |end: fn(&Range) -> &RangeEnd| node.range.as_ref().map_or(Cell::Null, |range| 
+            Cell::Number(end(range).expansion.line as u64));
+        self.nodes.render_row(&[
+            Cell::Number(node.id),
+            parent_id.map_or(Cell::Null, Cell::Number),
+            Cell::Number(child_index),
+            Cell::Text(node.kind.as_bytes()),
+            Cell::Text(node.name.as_bytes()),
+            Cell::Text(node.display_name.as_bytes()),
+            Cell::Text(node.usr.as_bytes()),
+            Cell::Text(node.location.as_bytes()),
+            type_name.map_or(Cell::Null, Cell::Text),
+            line(|range| &range.begin),
+            line(|range| &range.end),
+            Cell::Bool(node.truncated),
+        ])?;
+        for (key, link) in node.get_links() {
+            let (target, usr) = match link {
+                Some(Link::Id(id)) => (Cell::Number(*id), Cell::Null),
+                Some(Link::Usr(usr)) => (Cell::Null, Cell::Text(usr.as_bytes())),
+                None => continue,
+            };
+            self.edges.render_row(&[Cell::Number(node.id), Cell::Text(key.get_key()), target, usr])?;
+        }
+        if let Some(type_info) = &node.type_ {
+            self.types.render_row(&[
+                Cell::Number(node.id),
+                Cell::Text(type_info.display_name.as_bytes()),
+                Cell::Text(type_info.canonical.as_bytes()),
+                Cell::Text(type_info.kind.as_bytes()),
+            ])?;
+        }
+        Ok(())
+    }
+
+    fn end_node(&mut self, _node: &Node) -> Result<()> {
+        Ok(())
     }
 }
 
+/// Calls the task on every entity accepted by the filter in pre-order, without descending into rejected entities. 
+/// An entity reachable through several parents is visited once per parent, its children only once. 
+/// The traversal keeps its own stack, so the depth of the AST is not limited by the thread's stack.
 pub fn visit_ast<'a, Task: FnMut(Entity<'a>), Filter: Fn(Entity<'a>) -> bool>
-    (parent: Entity<'a>, mut task_and_registry: (Task, HashSet<u64>), filter: &Filter) 
-    -> (Task, HashSet<u64>) {
-    let mut hasher = DefaultHasher::new();
-    if filter(parent) {
-        task_and_registry.0(parent);
-        parent.hash(&mut hasher);
-        if task_and_registry.1.insert(hasher.finish()) {
-            for child in parent.get_children() {
-                task_and_registry = visit_ast(child, task_and_registry, filter.clone());
+    (parent: Entity<'a>, task_and_registry: (Task, HashSet<Entity<'a>>), filter: &Filter) 
+    -> (Task, HashSet<Entity<'a>>) {
+    visit_tree(parent, |entity| entity.get_children(), task_and_registry, filter)
+}
+
+// the traversal of `visit_ast` over any tree; visited nodes are kept themselves, not their hashes, 
+// since libclang's cursor hash is only 32 bits wide and distinct entities of a large translation unit share it
+fn visit_tree<T: Copy + Eq + Hash, Task: FnMut(T), Filter: Fn(T) -> bool>
+    (parent: T, get_children: impl Fn(T) -> Vec<T>, mut task_and_registry: (Task, HashSet<T>), filter: &Filter) 
+    -> (Task, HashSet<T>) {
+    let mut pending = vec![parent];
+    while let Some(node) = pending.pop() {
+        if filter(node) {
+            task_and_registry.0(node);
+            if task_and_registry.1.insert(node) {
+                // reversed, so the first child is popped first
+                pending.extend(get_children(node).into_iter().rev());
             }
         }
     }
     task_and_registry
 }
 
-fn should_be_excluded(entity: Entity, exclude_dirs: Vec<String>) -> bool {
-    exclude_dirs
-    .into_iter()
-    .map(|path| entity.get_location().get_name().contains(&path))
-    .collect::<Vec<bool>>()
-    .contains(&true) || entity.get_location().get_name().contains("include")
+/// Which entities are left out of the AST and of the collected project-wide data. 
+/// Entities of the main file are kept, unless they are preprocessing entities.
+#[derive(Clone, Debug, Default)]
+pub struct Exclusion {
+    /// Skip entities declared in system headers, as classified by clang (`-isystem` and builtin include paths).
+    pub system_headers: bool,
+    /// Skip entities declared in any file other than the main file.
+    pub other_files: bool,
+    /// Skip entities declared in files below these directories, normalized and compared component by component.
+    pub prefixes: Vec<PathBuf>,
+    /// Skip inclusion directives and macro definitions and expansions, main file included. 
+    /// libclang only reports them when the translation unit has a detailed preprocessing record.
+    pub preprocessing: bool,
 }
 
-#[derive(Clone)]
+pub fn should_be_excluded(entity: Entity, exclusion: &Exclusion) -> bool {
+    if exclusion.preprocessing && entity.is_preprocessing() {
+        return true;
+    }
+    let Some(location) = entity.get_location() else {
+        return false;
+    };
+    if location.is_in_main_file() {
+        return false;
+    }
+    if exclusion.other_files || (exclusion.system_headers && location.is_in_system_header()) {
+        return true;
+    }
+    location.get_file_location().file.is_some_and(|file| {
+        let path = normalize_path(&file.get_name());
+        exclusion.prefixes.iter().any(|prefix| path.starts_with(prefix))
+    })
+}
+
+// a node being written, whose children are not all written yet
+struct Frame<'tu> {
+    node: Node,
+    children: std::vec::IntoIter<Entity<'tu>>,
+    next_child_index: u64,
+}
+
+/// AST of a translation unit, written node by node during a second traversal of libclang's tree. 
+/// The first pass assigns the IDs, so links can point to nodes written later. Memory is linear in the number of cursors, 
+/// since their IDs are kept until the end, but nodes are dropped as soon as they end. 
+/// The traversals keep their own stack, so the depth of the AST is not limited by the thread's stack.
 pub struct AST<'tu> {
-    _root: Entity<'tu>,
-    pub nodes: HashSet<Entity<'tu>>
+    root: Entity<'tu>,
+    exclusion: Exclusion,
+    // nodes at this depth, the root being at 0, are written without their children
+    max_depth: Option<usize>,
+    diagnostics: Option<Vec<DiagnosticInfo>>,
+}
+
+impl WriteDocument for AST<'_> {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        self.write(json)
+    }
+}
+
+impl<'tu> AST<'tu> {
+    /// The AST below `root`, without the excluded entities and without the nodes deeper than `max_depth`.
+    pub fn new(root: Entity<'tu>, exclusion: &Exclusion, max_depth: Option<usize>) -> AST<'tu> {
+        AST { root, exclusion: exclusion.clone(), max_depth, diagnostics: None }
+    }
+
+    /// Diagnostics written with the root node.
+    pub fn set_diagnostics(&mut self, diagnostics: Vec<DiagnosticInfo>) {
+        self.diagnostics = Some(diagnostics);
+    }
+
+    // children of an entity at the given depth, none beyond the depth limit
+    fn get_children(&self, entity: Entity<'tu>, depth: usize) -> Vec<Entity<'tu>> {
+        match self.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+            true => Vec::new(),
+            false => entity.get_children(),
+        }
+    }
+
+    // IDs in pre-order of the entities written, an entity reachable through several parents is written under the first one only
+    fn assign_ids(&self) -> HashMap<Entity<'tu>, u64> {
+        let mut ids = HashMap::new();
+        if should_be_excluded(self.root, &self.exclusion) {
+            return ids;
+        }
+        ids.insert(self.root, 0);
+        let mut stack = vec![self.get_children(self.root, 0).into_iter()];
+        while let Some(children) = stack.last_mut() {
+            match children.next() {
+                Some(child) => {
+                    if !ids.contains_key(&child) && !should_be_excluded(child, &self.exclusion) {
+                        ids.insert(child, ids.len() as u64);
+                        let depth = stack.len();
+                        stack.push(self.get_children(child, depth).into_iter());
+                    }
+                },
+                None => {
+                    stack.pop();
+                },
+            }
+        }
+        ids
+    }
+
+    fn begin_entity<N: NodeWriter>(&self, entity: Entity<'tu>, parent_id: Option<u64>, child_index: u64, depth: usize, 
+        ids: &HashMap<Entity<'tu>, u64>, writer: &mut N) -> Result<Frame<'tu>> {
+        let id = ids[&entity];
+        let mut node = Node::new(entity, id, ids);
+        if parent_id.is_none() {
+            node.diagnostics = self.diagnostics.clone();
+        }
+        let children = self.get_children(entity, depth);
+        if self.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+            // children not written before this node, at this place or anywhere else
+            node.truncated = entity.get_children().iter().any(|child| 
+                ids.get(child).is_none_or(|child_id| *child_id > id) && !should_be_excluded(*child, &self.exclusion));
+        }
+        writer.begin_node(&node, parent_id, child_index)?;
+        Ok(Frame { node, children: children.into_iter(), next_child_index: 0 })
+    }
+
+    /// Hands the nodes to the writer while traversing libclang's tree.
+    pub fn write<N: NodeWriter>(&self, writer: &mut N) -> Result<()> {
+        let ids = self.assign_ids();
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let mut stack = vec![self.begin_entity(self.root, None, 0, 0, &ids, writer)?];
+        // the traversal is the one of `assign_ids`, so an entity is written where it got its ID
+        let mut written = 1;
+        while let Some(frame) = stack.last_mut() {
+            match frame.children.next() {
+                Some(child) => {
+                    if ids.get(&child) == Some(&written) {
+                        written += 1;
+                        let (parent_id, child_index) = (frame.node.id, frame.next_child_index);
+                        frame.next_child_index += 1;
+                        let depth = stack.len();
+                        stack.push(self.begin_entity(child, Some(parent_id), child_index, depth, &ids, writer)?);
+                    }
+                },
+                None => {
+                    let frame = stack.pop().unwrap();
+                    writer.end_node(&frame.node)?;
+                },
+            }
+        }
+        Ok(())
+    }
 }
 
-impl<'a> AST<'a> {
-    pub fn new(parent: Entity<'a>, exclude_dirs: Vec<String>) -> AST<'a> {
-        let mut nodes = HashSet::new();
-        let insertion_task = |entity: Entity<'a>| { nodes.insert(entity); };
-        let filter = |entity: Entity<'a>| { !should_be_excluded(entity.clone(), exclude_dirs.clone()) };
-        let _ = visit_ast(parent, (insertion_task, HashSet::new()), &filter).0;
-        AST { _root: parent, nodes: nodes }
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn node(id: u64) -> Node {
+        Node {
+            id,
+            kind: String::from("CompoundStmt"),
+            name: String::new(),
+            display_name: String::new(),
+            usr: String::new(),
+            location: String::from("a.cpp:1:1"),
+            type_: None,
+            range: None,
+            referenced: None,
+            definition: None,
+            canonical: None,
+            semantic_parent: Some(Link::Id(id.saturating_sub(1))),
+            lexical_parent: None,
+            truncated: false,
+            diagnostics: None,
+        }
     }
-}
\ No newline at end of file
+
+    // a chain of nested nodes, each one the only child of the previous one
+    pub(crate) fn deep_tree(depth: u64) -> Tree {
+        let mut builder = TreeBuilder::default();
+        for id in 0..depth {
+            builder.begin_node(&node(id), id.checked_sub(1), 0).unwrap();
+        }
+        for id in (0..depth).rev() {
+            builder.end_node(&node(id)).unwrap();
+        }
+        builder.into_tree().unwrap()
+    }
+
+    // a node of a tree whose nodes all have the same hash, as colliding cursors do
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    struct Colliding(u32);
+
+    impl Hash for Colliding {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            0u32.hash(state);
+        }
+    }
+
+    #[test]
+    fn nodes_with_the_same_hash_are_all_visited() {
+        // 0 has children 1 and 2, which share child 3; 4 is below 3
+        let get_children = |node: Colliding| match node.0 {
+            0 => vec![Colliding(1), Colliding(2)],
+            1 | 2 => vec![Colliding(3)],
+            3 => vec![Colliding(4)],
+            _ => Vec::new(),
+        };
+        let mut visited = Vec::new();
+        let (_, registry) = visit_tree(Colliding(0), get_children, (|node: Colliding| visited.push(node.0), HashSet::new()), &|_| true);
+        assert_eq!(registry.len(), 5);
+        assert_eq!(visited, [0, 1, 3, 4, 2, 3]);
+    }
+
+    #[test]
+    fn deep_tree_is_cloned_compared_and_serialized_without_overflow() {
+        let tree = deep_tree(300_000);
+        let copy = tree.clone();
+        assert_eq!(tree, copy);
+        assert_eq!(copy.iter().count(), 300_000);
+        let json = serde_json::to_string(&tree).unwrap();
+        assert!(json.starts_with(r#"{"formatVersion":1,"id":0,"kind":"CompoundStmt""#));
+        assert_eq!(json.matches("formatVersion").count(), 1);
+        assert_eq!(json.matches(r#""children":[]"#).count(), 1);
+    }
+
+    #[test]
+    fn trees_differing_in_shape_are_not_equal() {
+        let mut flat = deep_tree(1);
+        flat.children = vec![deep_tree(1), deep_tree(1)];
+        let mut nested = deep_tree(1);
+        let mut child = deep_tree(1);
+        child.children.push(deep_tree(1));
+        nested.children.push(child);
+        assert_ne!(flat, nested);
+        assert_eq!(flat, flat.clone());
+    }
+}
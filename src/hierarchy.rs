@@ -0,0 +1,288 @@
+/* 
+   Copyright (c) 2022 ArSysOp.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+  
+       http:  www.apache.org/licenses/LICENSE-2.0
+  
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+  
+   SPDX-License-Identifier: Apache-2.0
+  
+   Contributors:
+     ArSysOp - initial API and implementation
+*/
+
+use clang::{Entity, EntityKind};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::{Result, Write};
+use crate::graph::{visit_ast, should_be_excluded, Exclusion, Array, Key, WriteDocument};
+use crate::json::{Bracket, DocumentSerializer};
+use crate::kind::{get_accessibility_label, get_kind_label, RECORD_KINDS};
+use crate::get_name::*;
+
+fn is_final(entity: Entity) -> bool {
+    entity.get_children().iter().any(|child| child.get_kind() == EntityKind::FinalAttr)
+}
+
+#[derive(Clone, Debug)]
+struct Base {
+    usr: String,
+    name: String,
+    access: String,
+    is_virtual: bool,
+}
+
+impl WriteDocument for Base {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line(Key::Usr.get_key(), self.usr.as_bytes())?;
+        json.render_line(Key::Name.get_key(), self.name.as_bytes())?;
+        json.render_line(Key::Access.get_key(), self.access.as_bytes())?;
+        json.render_bool_line(Key::Virtual.get_key(), self.is_virtual)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+impl Base {
+    fn new(specifier: Entity) -> Base {
+        let declaration = specifier.get_type()
+        .and_then(|base_type| base_type.get_declaration())
+        .map(|declaration| declaration.get_canonical_entity());
+        Base {
+            usr: declaration.and_then(get_usr).unwrap_or_default(),
+            name: specifier.get_type().get_name(),
+            access: specifier.get_accessibility().map(get_accessibility_label).unwrap_or_default(),
+            is_virtual: specifier.is_virtual_base(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Method {
+    name: String,
+    is_pure: bool,
+    is_final: bool,
+    overrides: BTreeSet<String>,
+}
+
+struct MethodEntry<'a>(&'a String, &'a Method);
+
+impl WriteDocument for MethodEntry<'_> {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        let (usr, method) = (self.0, self.1);
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line(Key::Usr.get_key(), usr.as_bytes())?;
+        json.render_line(Key::Name.get_key(), method.name.as_bytes())?;
+        json.render_bool_line(Key::Pure.get_key(), method.is_pure)?;
+        json.render_bool_line(Key::Final.get_key(), method.is_final)?;
+        Array::new(Key::Overrides, method.overrides.iter().cloned().collect()).serialize(json)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Class {
+    name: String,
+    kind: String,
+    location: String,
+    is_abstract: bool,
+    is_final: bool,
+    bases: Vec<Base>,
+    virtual_methods: BTreeMap<String, Method>,
+}
+
+struct ClassEntry<'a>(&'a String, &'a Class, BTreeSet<String>);
+
+impl WriteDocument for ClassEntry<'_> {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        let (usr, class) = (self.0, self.1);
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line(Key::Usr.get_key(), usr.as_bytes())?;
+        json.render_line(Key::Name.get_key(), class.name.as_bytes())?;
+        json.render_line(Key::Kind.get_key(), class.kind.as_bytes())?;
+        json.render_line(Key::Location.get_key(), class.location.as_bytes())?;
+        json.render_bool_line(Key::Abstract.get_key(), class.is_abstract)?;
+        json.render_bool_line(Key::Final.get_key(), class.is_final)?;
+        Array::new(Key::Bases, class.bases.clone()).serialize(json)?;
+        Array::new(Key::Derived, self.2.iter().cloned().collect()).serialize(json)?;
+        json.render_line_without_value(Key::Methods.get_key())?;
+        json.render_bracket(Bracket::LBrace)?;
+        json.prefix.expand();
+        for (usr, method) in &class.virtual_methods {
+            MethodEntry(usr, method).serialize(json)?;
+        }
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RBrace)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+/// Every class definition of the project with its bases and virtual methods, ordered by USR. 
+/// Like the bases, the derived classes of a class are only its direct subclasses, 
+/// the whole hierarchy is found by following them.
+#[derive(Clone, Debug, Default)]
+pub struct ClassHierarchy {
+    classes: BTreeMap<String, Class>,
+}
+
+impl ClassHierarchy {
+    fn record(&mut self, entity: Entity) {
+        // unions neither derive nor are derived from
+        let kind = entity.get_kind();
+        if !RECORD_KINDS.contains(&kind) || kind == EntityKind::UnionDecl || !entity.is_definition() {
+            return;
+        }
+        let Some(usr) = get_usr(entity) else {
+            return;
+        };
+        let mut class = Class {
+            name: return_empty_if_null(entity.get_display_name()),
+            kind: get_kind_label(entity.get_kind()),
+            location: entity.get_location().get_name(),
+            is_abstract: entity.is_abstract_record(),
+            is_final: is_final(entity),
+            bases: Vec::new(),
+            virtual_methods: BTreeMap::new(),
+        };
+        for child in entity.get_children() {
+            match child.get_kind() {
+                EntityKind::BaseSpecifier => class.bases.push(Base::new(child)),
+                EntityKind::Method | EntityKind::Destructor if child.is_virtual_method() => {
+                    if let Some(method_usr) = get_usr(child) {
+                        class.virtual_methods.insert(method_usr, Method {
+                            name: return_empty_if_null(child.get_display_name()),
+                            is_pure: child.is_pure_virtual_method(),
+                            is_final: is_final(child),
+                            overrides: child.get_overridden_methods()
+                                .unwrap_or_default()
+                                .into_iter()
+                                .filter_map(get_usr)
+                                .collect(),
+                        });
+                    }
+                },
+                _ => {},
+            }
+        }
+        self.classes.insert(usr, class);
+    }
+
+    /// Merges the classes of another translation unit, a class defined in a header is kept once.
+    pub fn merge(&mut self, other: ClassHierarchy) {
+        for (usr, class) in other.classes {
+            self.classes.entry(usr).or_insert(class);
+        }
+    }
+
+    // direct subclasses of every base, by base USR
+    fn get_derived(&self) -> BTreeMap<&String, BTreeSet<String>> {
+        let mut derived: BTreeMap<&String, BTreeSet<String>> = BTreeMap::new();
+        for (usr, class) in &self.classes {
+            for base in &class.bases {
+                derived.entry(&base.usr).or_default().insert(usr.clone());
+            }
+        }
+        derived
+    }
+}
+
+impl WriteDocument for ClassHierarchy {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        let mut derived = self.get_derived();
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line_without_value(Key::Classes.get_key())?;
+        json.render_bracket(Bracket::LBrace)?;
+        json.prefix.expand();
+        for (usr, class) in &self.classes {
+            ClassEntry(usr, class, derived.remove(usr).unwrap_or_default()).serialize(json)?;
+        }
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RBrace)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+pub fn collect_classes(parent: Entity, exclusion: &Exclusion) -> ClassHierarchy {
+    let mut hierarchy = ClassHierarchy::default();
+    let recording_task = |entity: Entity| hierarchy.record(entity);
+    let filter = |entity: Entity| !should_be_excluded(entity, exclusion);
+    let _ = visit_ast(parent, (recording_task, HashSet::new()), &filter).0;
+    hierarchy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn class(location: &str, bases: &[&str]) -> Class {
+        Class {
+            name: String::new(),
+            kind: String::from("ClassDecl"),
+            location: String::from(location),
+            is_abstract: false,
+            is_final: false,
+            bases: bases.iter().map(|usr| Base {
+                usr: String::from(*usr),
+                name: String::new(),
+                access: String::from("public"),
+                is_virtual: false,
+            }).collect(),
+            virtual_methods: BTreeMap::new(),
+        }
+    }
+
+    fn hierarchy(classes: Vec<(&str, Class)>) -> ClassHierarchy {
+        ClassHierarchy { classes: classes.into_iter().map(|(usr, class)| (String::from(usr), class)).collect() }
+    }
+
+    fn derived(hierarchy: &ClassHierarchy, usr: &str) -> Vec<String> {
+        hierarchy.get_derived().remove(&String::from(usr)).unwrap_or_default().into_iter().collect()
+    }
+
+    #[test]
+    fn derived_classes_are_the_direct_subclasses() {
+        // B and C derive from A, D derives from both, E from D
+        let hierarchy = hierarchy(vec![
+            ("A", class("a.h:1:7", &[])),
+            ("B", class("b.h:1:7", &["A"])),
+            ("C", class("c.h:1:7", &["A"])),
+            ("D", class("d.h:1:7", &["B", "C"])),
+            ("E", class("e.h:1:7", &["D"])),
+        ]);
+        assert_eq!(derived(&hierarchy, "A"), ["B", "C"]);
+        assert_eq!(derived(&hierarchy, "B"), ["D"]);
+        assert_eq!(derived(&hierarchy, "C"), ["D"]);
+        assert_eq!(derived(&hierarchy, "D"), ["E"]);
+        assert!(derived(&hierarchy, "E").is_empty());
+    }
+
+    #[test]
+    fn class_defined_in_several_translation_units_is_kept_once() {
+        let mut merged = hierarchy(vec![("A", class("a.h:1:7", &[])), ("B", class("b.h:1:7", &["A"]))]);
+        merged.merge(hierarchy(vec![("B", class("other.h:1:7", &[])), ("C", class("c.h:1:7", &["A"]))]));
+        assert_eq!(merged.classes.keys().collect::<Vec<_>>(), ["A", "B", "C"]);
+        assert_eq!(merged.classes["B"].location, "b.h:1:7");
+        assert_eq!(derived(&merged, "A"), ["B", "C"]);
+        let mut json = DocumentSerializer::new(Vec::new());
+        merged.serialize(&mut json).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&json.into_inner()).unwrap();
+        assert_eq!(value["classes"][0]["derived"], serde_json::json!(["B", "C"]));
+        assert_eq!(value["classes"][1]["bases"][0]["usr"], "A");
+        assert_eq!(value["classes"][2]["derived"], serde_json::json!([]));
+    }
+}
@@ -0,0 +1,231 @@
+/* 
+   Copyright (c) 2022 ArSysOp.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+  
+       http:  www.apache.org/licenses/LICENSE-2.0
+  
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+  
+   SPDX-License-Identifier: Apache-2.0
+  
+   Contributors:
+     ArSysOp - initial API and implementation
+*/
+
+use clang::{EntityKind, TranslationUnit};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::io::{Result, Write};
+use crate::graph::{Array, Key, WriteDocument};
+use crate::json::{Bracket, DocumentSerializer};
+use crate::get_name::*;
+use crate::dot;
+
+#[derive(Clone, Debug, Default)]
+struct Edge {
+    system: bool,
+    locations: BTreeSet<String>,
+}
+
+/// Files included by other files, with the locations of the `#include` directives.
+/// Requires translation units parsed with a detailed preprocessing record.
+#[derive(Clone, Debug, Default)]
+pub struct IncludeGraph {
+    edges: BTreeMap<(String, String), Edge>,
+    system_headers: BTreeSet<String>,
+    // translation units including every header directly or transitively
+    included_by: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl IncludeGraph {
+    // breadth-first over the files included by every file, each edge is followed once
+    fn get_transitive_includes(&self, main_file: &str) -> BTreeSet<String> {
+        let mut included_by_file: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (includer, included) in self.edges.keys() {
+            included_by_file.entry(includer).or_default().push(included);
+        }
+        let mut reachable = BTreeSet::new();
+        let mut pending = VecDeque::from([main_file]);
+        while let Some(file) = pending.pop_front() {
+            for included in included_by_file.get(file).into_iter().flatten() {
+                if reachable.insert(String::from(*included)) {
+                    pending.push_back(included);
+                }
+            }
+        }
+        reachable
+    }
+
+    pub fn merge(&mut self, other: IncludeGraph) {
+        for (key, edge) in other.edges {
+            let existing = self.edges.entry(key).or_default();
+            existing.system |= edge.system;
+            existing.locations.extend(edge.locations);
+        }
+        self.system_headers.extend(other.system_headers);
+        for (header, translation_units) in other.included_by {
+            self.included_by.entry(header).or_default().extend(translation_units);
+        }
+    }
+
+    pub fn write_dot<W: Write>(&self, out: &mut W) -> Result<()> {
+        dot::render_header(out, "includes")?;
+        let files = self.edges.keys()
+        .flat_map(|(includer, included)| [includer, included])
+        .collect::<BTreeSet<&String>>();
+        for file in files {
+            let attributes = if self.system_headers.contains(file) { ", color=gray" } else { "" };
+            dot::render_node(out, file, file, attributes)?;
+        }
+        for ((includer, included), edge) in &self.edges {
+            dot::render_edge(out, includer, included, if edge.system { "color=gray" } else { "" })?;
+        }
+        dot::render_footer(out)
+    }
+}
+
+struct EdgeEntry<'a>(&'a String, &'a String, &'a Edge);
+
+impl WriteDocument for EdgeEntry<'_> {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line(Key::Includer.get_key(), self.0.as_bytes())?;
+        json.render_line(Key::File.get_key(), self.1.as_bytes())?;
+        json.render_bool_line(Key::System.get_key(), self.2.system)?;
+        Array::new(Key::Locations, self.2.locations.iter().cloned().collect()).serialize(json)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+struct HeaderEntry<'a>(&'a String, bool, &'a BTreeSet<String>);
+
+impl WriteDocument for HeaderEntry<'_> {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line(Key::File.get_key(), self.0.as_bytes())?;
+        json.render_bool_line(Key::System.get_key(), self.1)?;
+        Array::new(Key::IncludedBy, self.2.iter().cloned().collect()).serialize(json)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+fn serialize_edges<W: Write>(graph: &IncludeGraph, json: &mut DocumentSerializer<W>) -> Result<()> {
+    json.render_line_without_value(Key::Includes.get_key())?;
+    json.render_bracket(Bracket::LBrace)?;
+    json.prefix.expand();
+    for ((includer, included), edge) in &graph.edges {
+        EdgeEntry(includer, included, edge).serialize(json)?;
+    }
+    json.prefix.shrink();
+    json.render_bracket(Bracket::RBrace)
+}
+
+/// Project-wide graph with every header and the translation units pulling it in.
+impl WriteDocument for IncludeGraph {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line_without_value(Key::Headers.get_key())?;
+        json.render_bracket(Bracket::LBrace)?;
+        json.prefix.expand();
+        for (header, translation_units) in &self.included_by {
+            HeaderEntry(header, self.system_headers.contains(header), translation_units).serialize(json)?;
+        }
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RBrace)?;
+        serialize_edges(self, json)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+/// Include graph of a single translation unit, written next to its AST.
+pub struct TranslationUnitIncludes<'a> {
+    file: String,
+    graph: &'a IncludeGraph,
+}
+
+impl WriteDocument for TranslationUnitIncludes<'_> {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line(Key::File.get_key(), self.file.as_bytes())?;
+        serialize_edges(self.graph, json)?;
+        Array::new(Key::Transitive, self.graph.included_by.keys().cloned().collect()).serialize(json)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+impl TranslationUnitIncludes<'_> {
+    pub fn new(file: String, graph: &IncludeGraph) -> TranslationUnitIncludes<'_> {
+        TranslationUnitIncludes { file, graph }
+    }
+}
+
+pub fn collect_includes(tu: &TranslationUnit, tu_path: &str) -> IncludeGraph {
+    let mut graph = IncludeGraph::default();
+    for directive in tu.get_entity().get_children() {
+        if directive.get_kind() != EntityKind::InclusionDirective {
+            continue;
+        }
+        let Some(included) = directive.get_file() else {
+            continue;
+        };
+        let includer = directive.get_location().map(|location| location.get_file_location().file).get_name();
+        let system = included.get_location(1, 1).is_in_system_header();
+        if system {
+            graph.system_headers.insert(included.get_name());
+        }
+        let edge = graph.edges.entry((includer, included.get_name())).or_default();
+        edge.system = system;
+        edge.locations.insert(directive.get_location().get_name());
+    }
+    for header in graph.get_transitive_includes(&return_empty_if_null(tu.get_entity().get_name())) {
+        graph.included_by.entry(header).or_default().insert(String::from(tu_path));
+    }
+    graph
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(edges: &[(&str, &str)]) -> IncludeGraph {
+        let mut graph = IncludeGraph::default();
+        for (includer, included) in edges {
+            graph.edges.insert((String::from(*includer), String::from(*included)), Edge::default());
+        }
+        graph
+    }
+
+    fn transitive(graph: &IncludeGraph, main_file: &str) -> Vec<String> {
+        graph.get_transitive_includes(main_file).into_iter().collect()
+    }
+
+    #[test]
+    fn header_reached_through_a_diamond_is_included_once() {
+        let graph = graph(&[("a.cpp", "b.h"), ("a.cpp", "c.h"), ("b.h", "d.h"), ("c.h", "d.h"), ("d.h", "e.h"), ("x.cpp", "x.h")]);
+        assert_eq!(transitive(&graph, "a.cpp"), ["b.h", "c.h", "d.h", "e.h"]);
+        assert_eq!(transitive(&graph, "c.h"), ["d.h", "e.h"]);
+        assert!(transitive(&graph, "e.h").is_empty());
+    }
+
+    #[test]
+    fn cycle_of_includes_ends() {
+        // headers including each other, as with include guards
+        let graph = graph(&[("a.cpp", "b.h"), ("b.h", "c.h"), ("c.h", "b.h"), ("c.h", "d.h")]);
+        assert_eq!(transitive(&graph, "a.cpp"), ["b.h", "c.h", "d.h"]);
+        assert_eq!(transitive(&graph, "b.h"), ["b.h", "c.h", "d.h"]);
+    }
+}
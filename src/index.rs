@@ -20,25 +20,209 @@
 */
 
 use clang::Entity;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::{Result, Write};
+use crate::graph::{visit_ast, should_be_excluded, Exclusion, Array, Key, WriteDocument};
+use crate::json::{Bracket, Cell, DocumentSerializer, TableWriter};
+use crate::kind::get_kind_label;
+use crate::get_name::*;
 
-fn deref_in_set(set: HashSet<&String>) -> HashSet<String> {
-    let mut derefed: HashSet<String> = HashSet::new();
-    for element in set {
-        derefed.insert(element.to_string());
+#[derive(Clone, Debug, Default)]
+pub struct Symbol {
+    name: String,
+    kind: String,
+    definition: Option<String>,
+    declarations: BTreeSet<String>,
+    referenced_by: BTreeSet<String>,
+}
+
+impl Symbol {
+    // a second definition, as of an inline function seen again, keeps the first location
+    fn record_declaration(&mut self, location: String, is_definition: bool) {
+        if is_definition {
+            self.definition.get_or_insert(location);
+        } else {
+            self.declarations.insert(location);
+        }
+    }
+
+    fn merge(&mut self, other: Symbol) {
+        if self.definition.is_none() {
+            self.definition = other.definition;
+        }
+        self.declarations.extend(other.declarations);
+        self.referenced_by.extend(other.referenced_by);
+    }
+}
+
+struct SymbolEntry<'a>(&'a String, &'a Symbol);
+
+impl WriteDocument for SymbolEntry<'_> {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        let (usr, symbol) = (self.0, self.1);
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line(Key::Usr.get_key(), usr.as_bytes())?;
+        json.render_line(Key::Name.get_key(), symbol.name.as_bytes())?;
+        json.render_line(Key::Kind.get_key(), symbol.kind.as_bytes())?;
+        if let Some(definition) = &symbol.definition {
+            json.render_line(Key::Definition.get_key(), definition.as_bytes())?;
+        }
+        Array::new(Key::Declarations, symbol.declarations.iter().cloned().collect()).serialize(json)?;
+        Array::new(Key::ReferencedBy, symbol.referenced_by.iter().cloned().collect()).serialize(json)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
     }
-    derefed
 }
 
-pub fn collect_entities(parent: Entity) -> HashSet<String> {
-    let mut entities = HashSet::new();
-    let parent_usr = parent.get_usr();
-    match parent_usr {
-        Some(usr) => entities.insert(usr.0),
-        None => entities.insert(String::from("")),
-    };
-    for child in parent.get_children() {
-        entities = deref_in_set(entities.union(&collect_entities(child)).collect());
+/// Declarations, definitions and referencing translation units of every USR, ordered by USR.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolIndex {
+    symbols: BTreeMap<String, Symbol>,
+}
+
+impl WriteDocument for SymbolIndex {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        json.render_line_without_value(Key::Symbols.get_key())?;
+        json.render_bracket(Bracket::LBrace)?;
+        json.prefix.expand();
+        for (usr, symbol) in &self.symbols {
+            SymbolEntry(usr, symbol).serialize(json)?;
+        }
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RBrace)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
     }
-    entities
-}
\ No newline at end of file
+}
+
+pub const SYMBOL_COLUMNS: [Key; 4] = [Key::Usr, Key::Name, Key::Kind, Key::Definition];
+
+impl SymbolIndex {
+    /// Writes one row per symbol, ordered by USR.
+    pub fn write_rows<T: TableWriter>(&self, symbols: &mut T) -> Result<()> {
+        for (usr, symbol) in &self.symbols {
+            symbols.render_row(&[
+                Cell::Text(usr.as_bytes()),
+                Cell::Text(symbol.name.as_bytes()),
+                Cell::Text(symbol.kind.as_bytes()),
+                symbol.definition.as_ref().map_or(Cell::Null, |definition| Cell::Text(definition.as_bytes())),
+            ])?;
+        }
+        Ok(())
+    }
+
+    fn get_or_insert(&mut self, usr: String, entity: Entity) -> &mut Symbol {
+        self.symbols.entry(usr).or_insert_with(|| Symbol {
+            name: return_empty_if_null(entity.get_name()),
+            kind: get_kind_label(entity.get_kind()),
+            ..Symbol::default()
+        })
+    }
+
+    fn record(&mut self, entity: Entity, tu_path: &str) {
+        if entity.is_declaration() {
+            if let Some(usr) = entity.get_usr().filter(|usr| !usr.0.is_empty()) {
+                let location = entity.get_location().get_name();
+                self.get_or_insert(usr.0, entity).record_declaration(location, entity.is_definition());
+            }
+        } else if let Some(referenced) = entity.get_reference() {
+            if let Some(usr) = referenced.get_usr().filter(|usr| !usr.0.is_empty()) {
+                self.get_or_insert(usr.0, referenced).referenced_by.insert(String::from(tu_path));
+            }
+        }
+    }
+
+    /// Merges the symbols of another translation unit, the definition seen first wins.
+    pub fn merge(&mut self, other: SymbolIndex) {
+        for (usr, symbol) in other.symbols {
+            match self.symbols.get_mut(&usr) {
+                Some(existing) => existing.merge(symbol),
+                None => {
+                    self.symbols.insert(usr, symbol);
+                },
+            }
+        }
+    }
+}
+
+pub fn collect_entities(parent: Entity, tu_path: &str, exclusion: &Exclusion) -> SymbolIndex {
+    let mut symbols = SymbolIndex::default();
+    let recording_task = |entity: Entity| symbols.record(entity, tu_path);
+    let filter = |entity: Entity| !should_be_excluded(entity, exclusion);
+    let _ = visit_ast(parent, (recording_task, HashSet::new()), &filter).0;
+    symbols
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(definition: Option<&str>, declarations: &[&str], referenced_by: &[&str]) -> Symbol {
+        Symbol {
+            name: String::from("f"),
+            kind: String::from("FunctionDecl"),
+            definition: definition.map(String::from),
+            declarations: declarations.iter().map(|location| String::from(*location)).collect(),
+            referenced_by: referenced_by.iter().map(|path| String::from(*path)).collect(),
+        }
+    }
+
+    fn index(symbols: Vec<(&str, Symbol)>) -> SymbolIndex {
+        SymbolIndex { symbols: symbols.into_iter().map(|(usr, symbol)| (String::from(usr), symbol)).collect() }
+    }
+
+    fn symbol_json(index: &SymbolIndex) -> serde_json::Value {
+        let mut json = DocumentSerializer::new(Vec::new());
+        index.serialize(&mut json).unwrap();
+        serde_json::from_slice(&json.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn declaration_followed_by_definition_keeps_both_locations() {
+        let mut symbol = symbol(None, &[], &[]);
+        symbol.record_declaration(String::from("f.h:1:6"), false);
+        symbol.record_declaration(String::from("f.cpp:3:6"), true);
+        symbol.record_declaration(String::from("f.h:1:6"), false);
+        symbol.record_declaration(String::from("g.cpp:3:6"), true);
+        assert_eq!(symbol.definition.as_deref(), Some("f.cpp:3:6"));
+        assert_eq!(symbol.declarations.iter().collect::<Vec<_>>(), ["f.h:1:6"]);
+    }
+
+    #[test]
+    fn merged_indexes_keep_the_first_definition_of_a_usr() {
+        let mut merged = index(vec![
+            ("c:@F@f#", symbol(Some("a.cpp:1:6"), &["f.h:1:6"], &["a.cpp"])),
+        ]);
+        merged.merge(index(vec![
+            ("c:@F@f#", symbol(Some("b.cpp:1:6"), &["f.h:1:6", "b.cpp:9:6"], &["b.cpp"])),
+            ("c:@F@g#", symbol(None, &["g.h:1:6"], &["b.cpp"])),
+        ]));
+        assert_eq!(symbol_json(&merged), serde_json::json!({"symbols": [
+            {
+                "usr": "c:@F@f#",
+                "name": "f",
+                "kind": "FunctionDecl",
+                "definition": "a.cpp:1:6",
+                "declarations": ["b.cpp:9:6", "f.h:1:6"],
+                "referencedBy": ["a.cpp", "b.cpp"],
+            },
+            {
+                "usr": "c:@F@g#",
+                "name": "f",
+                "kind": "FunctionDecl",
+                "declarations": ["g.h:1:6"],
+                "referencedBy": ["b.cpp"],
+            },
+        ]}));
+    }
+
+    #[test]
+    fn definition_of_a_merged_index_fills_a_missing_one() {
+        let mut merged = index(vec![("c:@F@f#", symbol(None, &["f.h:1:6"], &[]))]);
+        merged.merge(index(vec![("c:@F@f#", symbol(Some("b.cpp:1:6"), &[], &[]))]));
+        assert_eq!(merged.symbols["c:@F@f#"].definition.as_deref(), Some("b.cpp:1:6"));
+    }
+}
@@ -19,9 +19,7 @@
      ArSysOp - initial API and implementation
 */
 
-use std::io::BufWriter;
-use std::io::Write;
-use std::fs::File;
+use std::io::{Result, Write};
 
 const INDENT: usize = 2;
 const FILL: char = ' ';
@@ -40,42 +38,41 @@ impl Prefix {
         }
     }
 
-    pub fn expand(self: &mut Self) {
+    pub fn expand(&mut self) {
         self.sz += INDENT;
         for _ in 0..INDENT {
             self.buf.push(FILL);
         }
     }
 
-    pub fn shrink(self: &mut Self) {
+    pub fn shrink(&mut self) {
         assert!(self.sz >= INDENT);
         self.sz -= INDENT;
         self.buf.truncate(self.sz);
     }
 }
 
-// thanks to @burubum from StackOverFlow 
-fn replace<T>(source: &[T], from: &[T], to: &[T]) -> Vec<T>
-where
-    T: Clone + PartialEq
-{
-    let mut result = source.to_vec();
-    let from_len = from.len();
-    let to_len = to.len();
-
-    let mut i = 0;
-    while i + from_len <= result.len() {
-        if result[i..].starts_with(from) {
-            result.splice(i..i + from_len, to.iter().cloned());
-            i += to_len;
-        } else {
-            i += 1;
+/// Escapes a string value as required by RFC 8259. 
+/// Invalid UTF-8 sequences are replaced with U+FFFD.
+pub fn escape(value: &[u8]) -> String {
+    let mut result = String::with_capacity(value.len());
+    for c in String::from_utf8_lossy(value).chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            '\u{8}' => result.push_str("\\b"),
+            '\u{c}' => result.push_str("\\f"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
         }
     }
-
     result
 }
 
+/// Opening or closing bracket of an array (`LBrace`, `RBrace`) or a map (`LCurly`, `RCurly`).
 pub enum Bracket {
     LBrace,
     RBrace,
@@ -83,46 +80,504 @@ pub enum Bracket {
     RCurly,
 }
 
-pub struct JSONSerializer {
+/// Encoding produced by `DocumentSerializer`. The binary encodings carry the same maps, arrays, 
+/// strings, numbers and booleans as the JSON output, without indentation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    /// RFC 8949, with indefinite-length maps and arrays.
+    Cbor,
+    MessagePack,
+}
+
+// MessagePack needs the number of members before a container, which is only known once it closes
+struct Container {
+    // index of its header in `MessagePackDocument::headers`
+    header: usize,
+    members: u32,
+    is_map: bool,
+}
+
+// the outermost MessagePack container being written, kept in memory without the headers of its containers, 
+// which are inserted at their positions when it is written out
+#[derive(Default)]
+struct MessagePackDocument {
+    buffer: Vec<u8>,
+    // positions in the buffer in increasing order, with the header once the container is closed
+    headers: Vec<(usize, Vec<u8>)>,
+}
+
+fn cbor_header(major: u8, value: u64) -> Vec<u8> {
+    let major = major << 5;
+    match value {
+        0..=23 => vec![major | value as u8],
+        24..=0xff => vec![major | 24, value as u8],
+        0x100..=0xffff => [&[major | 25][..], &(value as u16).to_be_bytes()].concat(),
+        0x10000..=0xffff_ffff => [&[major | 26][..], &(value as u32).to_be_bytes()].concat(),
+        _ => [&[major | 27][..], &value.to_be_bytes()].concat(),
+    }
+}
+
+fn message_pack_header(small: u8, small_limit: u32, codes: [u8; 3], length: u32) -> Vec<u8> {
+    match length {
+        length if length < small_limit => vec![small | length as u8],
+        // only strings have an 8-bit length form, the others pass 0
+        length if length <= 0xff && codes[0] != 0 => vec![codes[0], length as u8],
+        length if length <= 0xffff => [&[codes[1]][..], &(length as u16).to_be_bytes()].concat(),
+        length => [&[codes[2]][..], &length.to_be_bytes()].concat(),
+    }
+}
+
+fn message_pack_number(value: u64) -> Vec<u8> {
+    match value {
+        0..=0x7f => vec![value as u8],
+        0x80..=0xff => vec![0xcc, value as u8],
+        0x100..=0xffff => [&[0xcd][..], &(value as u16).to_be_bytes()].concat(),
+        0x10000..=0xffff_ffff => [&[0xce][..], &(value as u32).to_be_bytes()].concat(),
+        _ => [&[0xcf][..], &value.to_be_bytes()].concat(),
+    }
+}
+
+// negative values only, as 64-bit signed integers
+fn message_pack_negative_number(value: i64) -> Vec<u8> {
+    match value {
+        -32..=-1 => vec![value as u8],
+        -0x80..=-33 => vec![0xd0, value as u8],
+        -0x8000..=-0x81 => [&[0xd1][..], &(value as i16).to_be_bytes()].concat(),
+        -0x8000_0000..=-0x8001 => [&[0xd2][..], &(value as i32).to_be_bytes()].concat(),
+        _ => [&[0xd3][..], &value.to_be_bytes()].concat(),
+    }
+}
+
+/// Writer of nested documents: pretty-printed JSON, CBOR or MessagePack. 
+/// Commas between members are inserted automatically, so callers only open and close 
+/// containers and render their members. The writer is not buffered, wrap files into `BufWriter`. 
+/// JSON and CBOR are streamed. MessagePack is not: its containers start with the number of their members, 
+/// so the whole document is kept in memory and written when its outermost container closes.
+pub struct DocumentSerializer<W: Write> {
     pub prefix: Prefix,
-    pub writer: BufWriter<File>
+    writer: W,
+    encoding: Encoding,
+    // one entry per open container, true until its first member is written
+    first: Vec<bool>,
+    after_key: bool,
+    containers: Vec<Container>,
+    document: MessagePackDocument,
 }
 
-impl JSONSerializer {
-    pub fn new(out: File) -> JSONSerializer {
-        JSONSerializer {
+impl<W: Write> DocumentSerializer<W> {
+    /// A writer of pretty-printed JSON.
+    pub fn new(out: W) -> DocumentSerializer<W> {
+        DocumentSerializer::with_encoding(out, Encoding::Json)
+    }
+
+    pub fn with_encoding(out: W, encoding: Encoding) -> DocumentSerializer<W> {
+        DocumentSerializer {
             prefix: Prefix::new(),
-            writer: BufWriter::new(out)
+            writer: out,
+            encoding,
+            first: Vec::new(),
+            after_key: false,
+            containers: Vec::new(),
+            document: MessagePackDocument::default(),
         }
     }
 
-    fn write(self: &mut Self, bufs: &[&[u8]]) {
+    fn write(&mut self, bufs: &[&[u8]]) -> Result<()> {
         for buf in bufs {
-            self.writer.write(buf).unwrap();
+            match self.containers.is_empty() {
+                true => self.writer.write_all(buf)?,
+                false => self.document.buffer.extend_from_slice(buf),
+            }
+        }
+        Ok(())
+    }
+
+    fn write_document(&mut self) -> Result<()> {
+        let document = std::mem::take(&mut self.document);
+        let mut written = 0;
+        for (position, header) in &document.headers {
+            self.writer.write_all(&document.buffer[written..*position])?;
+            self.writer.write_all(header)?;
+            written = *position;
         }
+        self.writer.write_all(&document.buffer[written..])
+    }
+
+    fn begin_member(&mut self) -> Result<()> {
+        if self.after_key {
+            self.after_key = false;
+            return Ok(());
+        }
+        if let Some(container) = self.containers.last_mut() {
+            container.members += 1;
+        }
+        if let Some(first) = self.first.last_mut() {
+            if *first {
+                *first = false;
+            } else if self.encoding == Encoding::Json {
+                return self.write(&[b","]);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_string(&mut self, value: &[u8]) -> Result<()> {
+        let value = String::from_utf8_lossy(value);
+        let header = match self.encoding {
+            Encoding::Json => return self.write(&[b"\"", escape(value.as_bytes()).as_bytes(), b"\""]),
+            Encoding::Cbor => cbor_header(3, value.len() as u64),
+            Encoding::MessagePack => message_pack_header(0xa0, 32, [0xd9, 0xda, 0xdb], value.len() as u32),
+        };
+        self.write(&[&header, value.as_bytes()])
+    }
+
+    // containers start on the next line, scalar values on the same line as their key
+    fn write_key(&mut self, key: &[u8], separator: &[u8]) -> Result<()> {
+        if self.encoding == Encoding::Json {
+            let prefix = self.prefix.buf.clone();
+            self.write(&[b"\n", prefix.as_bytes()])?;
+            self.write_string(key)?;
+            return self.write(&[separator]);
+        }
+        self.write_string(key)
+    }
+
+    fn write_number(&mut self, value: u64) -> Result<()> {
+        match self.encoding {
+            Encoding::Json => self.write(&[value.to_string().as_bytes()]),
+            Encoding::Cbor => self.write(&[&cbor_header(0, value)]),
+            Encoding::MessagePack => self.write(&[&message_pack_number(value)]),
+        }
+    }
+
+    fn write_signed_number(&mut self, value: i64) -> Result<()> {
+        if value >= 0 {
+            return self.write_number(value as u64);
+        }
+        match self.encoding {
+            Encoding::Json => self.write(&[value.to_string().as_bytes()]),
+            Encoding::Cbor => self.write(&[&cbor_header(1, !value as u64)]),
+            Encoding::MessagePack => self.write(&[&message_pack_negative_number(value)]),
+        }
+    }
+
+    fn write_bool(&mut self, value: bool) -> Result<()> {
+        match (self.encoding, value) {
+            (Encoding::Json, true) => self.write(&[b"true"]),
+            (Encoding::Json, false) => self.write(&[b"false"]),
+            (Encoding::Cbor, true) => self.write(&[&[0xf5]]),
+            (Encoding::Cbor, false) => self.write(&[&[0xf4]]),
+            (Encoding::MessagePack, true) => self.write(&[&[0xc3]]),
+            (Encoding::MessagePack, false) => self.write(&[&[0xc2]]),
+        }
+    }
+
+    /// Member of a map with a string value.
+    pub fn render_line(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.begin_member()?;
+        self.write_key(key, b": ")?;
+        self.write_string(value)
+    }
+
+    /// String member of an array.
+    pub fn render_value(&mut self, value: &[u8]) -> Result<()> {
+        self.begin_member()?;
+        if self.encoding == Encoding::Json {
+            let prefix = self.prefix.buf.clone();
+            self.write(&[b"\n", prefix.as_bytes()])?;
+        }
+        self.write_string(value)
+    }
+
+    pub fn render_number_line(&mut self, key: &[u8], value: u64) -> Result<()> {
+        self.begin_member()?;
+        self.write_key(key, b": ")?;
+        self.write_number(value)
     }
 
-    pub fn render_line(self: &mut Self, key: &[u8], value: &[u8]) {
-        let value_with_escaped_quotes = replace(value, b"\"", b"\\\"");
-        self.write(&[b"\n", self.prefix.clone().buf.as_bytes(), b"\"", key, b"\": \"", value_with_escaped_quotes.as_slice(), b"\","]);
+    pub fn render_signed_number_line(&mut self, key: &[u8], value: i64) -> Result<()> {
+        self.begin_member()?;
+        self.write_key(key, b": ")?;
+        self.write_signed_number(value)
     }
 
-    pub fn render_line_without_value(self: &mut Self, key: &[u8]) {
-        self.write(&[b"\n", self.prefix.clone().buf.as_bytes(), b"\"", key, b"\":"]);
+    pub fn render_bool_line(&mut self, key: &[u8], value: bool) -> Result<()> {
+        self.begin_member()?;
+        self.write_key(key, b": ")?;
+        self.write_bool(value)
     }
 
-    pub fn render_comma(self: &mut Self) {
-        self.write(&[b","]);
+    /// Key of a member whose value, a map or an array, is rendered next.
+    pub fn render_line_without_value(&mut self, key: &[u8]) -> Result<()> {
+        self.begin_member()?;
+        self.write_key(key, b":")?;
+        self.after_key = true;
+        Ok(())
     }
 
-    pub fn render_bracket(self: &mut Self, br: Bracket) {
-        let self_prefix = self.prefix.clone();
+    /// Opens or closes a container, opening one starts a member of the enclosing container.
+    pub fn render_bracket(&mut self, br: Bracket) -> Result<()> {
+        let is_map = matches!(br, Bracket::LCurly | Bracket::RCurly);
         match br {
-            Bracket::LBrace => self.write(&[b"\n", self_prefix.buf.as_bytes(), b"["]),
-            Bracket::RBrace => self.write(&[b"\n", self_prefix.buf.as_bytes(), b"]"]),
-            Bracket::LCurly => self.write(&[b"\n", self_prefix.buf.as_bytes(), b"{"]),
-            Bracket::RCurly => self.write(&[b"\n", self_prefix.buf.as_bytes(), b"}"]),
+            Bracket::LBrace | Bracket::LCurly => {
+                self.begin_member()?;
+                self.first.push(true);
+            },
+            Bracket::RBrace | Bracket::RCurly => {
+                self.first.pop();
+            },
+        }
+        match (self.encoding, br) {
+            (Encoding::Json, br) => {
+                let bracket: &[u8] = match br {
+                    Bracket::LBrace => b"[",
+                    Bracket::RBrace => b"]",
+                    Bracket::LCurly => b"{",
+                    Bracket::RCurly => b"}",
+                };
+                let prefix = self.prefix.buf.clone();
+                self.write(&[b"\n", prefix.as_bytes(), bracket])
+            },
+            (Encoding::Cbor, Bracket::LBrace | Bracket::LCurly) => self.write(&[&[if is_map { 0xbf } else { 0x9f }]]),
+            (Encoding::Cbor, Bracket::RBrace | Bracket::RCurly) => self.write(&[&[0xff]]),
+            (Encoding::MessagePack, Bracket::LBrace | Bracket::LCurly) => {
+                self.document.headers.push((self.document.buffer.len(), Vec::new()));
+                self.containers.push(Container { header: self.document.headers.len() - 1, members: 0, is_map });
+                Ok(())
+            },
+            (Encoding::MessagePack, Bracket::RBrace | Bracket::RCurly) => {
+                let container = self.containers.pop().expect("unbalanced brackets");
+                self.document.headers[container.header].1 = if container.is_map {
+                    message_pack_header(0x80, 16, [0, 0xde, 0xdf], container.members)
+                } else {
+                    message_pack_header(0x90, 16, [0, 0xdc, 0xdd], container.members)
+                };
+                match self.containers.is_empty() {
+                    true => self.write_document(),
+                    false => Ok(()),
+                }
+            },
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+
+    /// The underlying writer, once the document is complete.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Layout of the files holding the AST of every translation unit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Nested tree, one file per translation unit.
+    Tree(Encoding),
+    /// Flat node and edge tables, two files per translation unit.
+    Table(TableFormat),
+    /// One SQLite database with the tables of all translation units.
+    Sqlite,
+}
+
+/// Encoding of flat tables.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableFormat {
+    /// One JSON object per row and line.
+    JsonLines,
+    /// Comma-separated values as described in RFC 4180, with a header row.
+    Csv,
+}
+
+impl TableFormat {
+    pub fn get_extension(&self) -> &'static str {
+        match self {
+            TableFormat::JsonLines => ".jsonl",
+            TableFormat::Csv => ".csv",
         }
     }
 }
 
+pub enum Cell<'a> {
+    Text(&'a [u8]),
+    Number(u64),
+    Bool(bool),
+    Null,
+}
+
+fn escape_csv(value: &[u8]) -> String {
+    let value = String::from_utf8_lossy(value);
+    if value.contains(['"', ',', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Destination of the rows of a flat table with a fixed set of columns.
+pub trait TableWriter {
+    /// Cells are given in the order of the columns.
+    fn render_row(&mut self, cells: &[Cell]) -> Result<()>;
+}
+
+/// Writer of flat tables with a fixed set of columns, one row per line. 
+/// The writer is not buffered, wrap files into `BufWriter`.
+pub struct TableSerializer<W: Write> {
+    writer: W,
+    format: TableFormat,
+    columns: Vec<&'static [u8]>,
+}
+
+impl<W: Write> TableSerializer<W> {
+    pub fn new(mut out: W, format: TableFormat, columns: Vec<&'static [u8]>) -> Result<TableSerializer<W>> {
+        if format == TableFormat::Csv {
+            let header = columns.iter().map(|column| escape_csv(column)).collect::<Vec<String>>().join(",");
+            out.write_all(header.as_bytes())?;
+            out.write_all(b"\r\n")?;
+        }
+        Ok(TableSerializer { writer: out, format, columns })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> TableWriter for TableSerializer<W> {
+    fn render_row(&mut self, cells: &[Cell]) -> Result<()> {
+        assert_eq!(cells.len(), self.columns.len());
+        let row = match self.format {
+            TableFormat::JsonLines => {
+                let members = self.columns.iter().zip(cells).map(|(column, cell)| {
+                    let value = match cell {
+                        Cell::Text(text) => format!("\"{}\"", escape(text)),
+                        Cell::Number(number) => number.to_string(),
+                        Cell::Bool(value) => value.to_string(),
+                        Cell::Null => String::from("null"),
+                    };
+                    format!("\"{}\":{}", escape(column), value)
+                }).collect::<Vec<String>>();
+                format!("{{{}}}\n", members.join(","))
+            },
+            TableFormat::Csv => {
+                let values = cells.iter().map(|cell| match cell {
+                    Cell::Text(text) => escape_csv(text),
+                    Cell::Number(number) => number.to_string(),
+                    Cell::Bool(value) => value.to_string(),
+                    Cell::Null => String::new(),
+                }).collect::<Vec<String>>();
+                format!("{}\r\n", values.join(","))
+            },
+        };
+        self.writer.write_all(row.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_document(encoding: Encoding) -> Vec<u8> {
+        let mut json = DocumentSerializer::with_encoding(Vec::new(), encoding);
+        json.render_bracket(Bracket::LCurly).unwrap();
+        json.render_line(b"name", b"f").unwrap();
+        json.render_line_without_value(b"children").unwrap();
+        json.render_bracket(Bracket::LBrace).unwrap();
+        for _ in 0..2 {
+            json.render_bracket(Bracket::LCurly).unwrap();
+            json.render_number_line(b"id", 1).unwrap();
+            json.render_bool_line(b"pure", true).unwrap();
+            json.render_bracket(Bracket::RCurly).unwrap();
+        }
+        json.render_bracket(Bracket::RBrace).unwrap();
+        json.render_signed_number_line(b"value", -2).unwrap();
+        json.render_bracket(Bracket::RCurly).unwrap();
+        json.into_inner()
+    }
+
+    #[test]
+    fn message_pack_headers_are_inserted_before_their_members() {
+        let expected = [
+            &[0x83, 0xa4][..], b"name", &[0xa1], b"f", &[0xa8], b"children", &[0x92],
+            &[0x82, 0xa2], b"id", &[0x01, 0xa4], b"pure", &[0xc3],
+            &[0x82, 0xa2], b"id", &[0x01, 0xa4], b"pure", &[0xc3],
+            &[0xa5], b"value", &[0xfe],
+        ].concat();
+        assert_eq!(write_document(Encoding::MessagePack), expected);
+    }
+
+    #[test]
+    fn cbor_containers_have_indefinite_length() {
+        let expected = [
+            &[0xbf, 0x64][..], b"name", &[0x61], b"f", &[0x68], b"children", &[0x9f],
+            &[0xbf, 0x62], b"id", &[0x01, 0x64], b"pure", &[0xf5, 0xff],
+            &[0xbf, 0x62], b"id", &[0x01, 0x64], b"pure", &[0xf5, 0xff],
+            &[0xff, 0x65], b"value", &[0x21, 0xff],
+        ].concat();
+        assert_eq!(write_document(Encoding::Cbor), expected);
+    }
+
+    #[test]
+    fn json_document_is_valid() {
+        let value: serde_json::Value = serde_json::from_slice(&write_document(Encoding::Json)).unwrap();
+        assert_eq!(value, serde_json::json!({
+            "name": "f",
+            "children": [{"id": 1, "pure": true}, {"id": 1, "pure": true}],
+            "value": -2,
+        }));
+    }
+
+    #[test]
+    fn strings_are_escaped_as_in_rfc_8259() {
+        assert_eq!(escape(b"a \"b\" \\ c/d"), "a \\\"b\\\" \\\\ c/d");
+        assert_eq!(escape(b"\n\r\t\x08\x0c\x01\x1f"), "\\n\\r\\t\\b\\f\\u0001\\u001f");
+        assert_eq!(escape("\u{7f} \u{e9} \u{1f600}".as_bytes()), "\u{7f} \u{e9} \u{1f600}");
+        assert_eq!(escape(b"a\xffb"), "a\u{fffd}b");
+        let value = b"\"\\\n\x00 std::vector<int>";
+        let parsed: String = serde_json::from_str(&format!("\"{}\"", escape(value))).unwrap();
+        assert_eq!(parsed.as_bytes(), value);
+    }
+
+    #[test]
+    fn csv_cells_are_quoted_only_when_needed() {
+        assert_eq!(escape_csv(b"std::vector<int>"), "std::vector<int>");
+        assert_eq!(escape_csv(b"f(int, long)"), "\"f(int, long)\"");
+        assert_eq!(escape_csv(b"say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(escape_csv(b"a\nb"), "\"a\nb\"");
+        assert_eq!(escape_csv(b"a\rb"), "\"a\rb\"");
+        assert_eq!(escape_csv(b""), "");
+    }
+
+    #[test]
+    fn headers_use_the_shortest_form_for_their_length() {
+        assert_eq!(cbor_header(0, 23), [0x17]);
+        assert_eq!(cbor_header(0, 24), [0x18, 24]);
+        assert_eq!(cbor_header(3, 0xff), [0x78, 0xff]);
+        assert_eq!(cbor_header(3, 0x100), [0x79, 0x01, 0x00]);
+        assert_eq!(cbor_header(0, 0x10000), [0x1a, 0x00, 0x01, 0x00, 0x00]);
+        assert_eq!(cbor_header(1, 0x1_0000_0000), [0x3b, 0, 0, 0, 0x01, 0, 0, 0, 0]);
+        let string = |length| message_pack_header(0xa0, 32, [0xd9, 0xda, 0xdb], length);
+        assert_eq!(string(31), [0xbf]);
+        assert_eq!(string(32), [0xd9, 32]);
+        assert_eq!(string(0x100), [0xda, 0x01, 0x00]);
+        assert_eq!(string(0x10000), [0xdb, 0x00, 0x01, 0x00, 0x00]);
+        let map = |length| message_pack_header(0x80, 16, [0, 0xde, 0xdf], length);
+        assert_eq!(map(15), [0x8f]);
+        assert_eq!(map(16), [0xde, 0x00, 16]);
+        assert_eq!(map(0x10000), [0xdf, 0x00, 0x01, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn message_pack_arrays_of_more_than_15_members_get_a_wider_header() {
+        let mut json = DocumentSerializer::with_encoding(Vec::new(), Encoding::MessagePack);
+        json.render_bracket(Bracket::LBrace).unwrap();
+        for _ in 0..20 {
+            json.render_bracket(Bracket::LBrace).unwrap();
+            json.render_bracket(Bracket::RBrace).unwrap();
+        }
+        json.render_bracket(Bracket::RBrace).unwrap();
+        assert_eq!(json.into_inner(), [&[0xdc, 0x00, 20][..], &[0x90; 20]].concat());
+    }
+}
@@ -21,6 +21,15 @@
 
 use clang::EntityKind::*;
 use clang::EntityKind;
+use clang::TypeKind;
+use clang::diagnostic::Severity;
+use clang::Accessibility;
+
+/// Kinds of the declarations of functions, methods and function templates.
+pub const FUNCTION_KINDS: [EntityKind; 6] = [FunctionDecl, Method, Constructor, Destructor, ConversionFunction, FunctionTemplate];
+
+/// Kinds of the declarations of classes, structs, unions and class templates.
+pub const RECORD_KINDS: [EntityKind; 5] = [ClassDecl, StructDecl, UnionDecl, ClassTemplate, ClassTemplatePartialSpecialization];
 
 pub fn get_kind_label(kind_name: EntityKind) -> String {
     match kind_name {
@@ -267,4 +276,142 @@ pub fn get_kind_label(kind_name: EntityKind) -> String {
         FriendDecl => String::from("FriendDecl"),
         OverloadCandidate => String::from("OverloadCandidate"),
     } 
-}
\ No newline at end of file
+}
+
+pub fn get_type_kind_label(kind_name: TypeKind) -> String {
+    match kind_name {
+        TypeKind::Unexposed => String::from("Unexposed"),
+        TypeKind::Void => String::from("Void"),
+        TypeKind::Bool => String::from("Bool"),
+        TypeKind::CharS => String::from("CharS"),
+        TypeKind::CharU => String::from("CharU"),
+        TypeKind::SChar => String::from("SChar"),
+        TypeKind::UChar => String::from("UChar"),
+        TypeKind::WChar => String::from("WChar"),
+        TypeKind::Char16 => String::from("Char16"),
+        TypeKind::Char32 => String::from("Char32"),
+        TypeKind::Short => String::from("Short"),
+        TypeKind::UShort => String::from("UShort"),
+        TypeKind::Int => String::from("Int"),
+        TypeKind::UInt => String::from("UInt"),
+        TypeKind::Long => String::from("Long"),
+        TypeKind::ULong => String::from("ULong"),
+        TypeKind::LongLong => String::from("LongLong"),
+        TypeKind::ULongLong => String::from("ULongLong"),
+        TypeKind::Int128 => String::from("Int128"),
+        TypeKind::UInt128 => String::from("UInt128"),
+        TypeKind::Half => String::from("Half"),
+        TypeKind::Float16 => String::from("Float16"),
+        TypeKind::ShortAccum => String::from("ShortAccum"),
+        TypeKind::Accum => String::from("Accum"),
+        TypeKind::LongAccum => String::from("LongAccum"),
+        TypeKind::UShortAccum => String::from("UShortAccum"),
+        TypeKind::UAccum => String::from("UAccum"),
+        TypeKind::ULongAccum => String::from("ULongAccum"),
+        TypeKind::Float => String::from("Float"),
+        TypeKind::Double => String::from("Double"),
+        TypeKind::LongDouble => String::from("LongDouble"),
+        TypeKind::Nullptr => String::from("Nullptr"),
+        TypeKind::Complex => String::from("Complex"),
+        TypeKind::Dependent => String::from("Dependent"),
+        TypeKind::Overload => String::from("Overload"),
+        TypeKind::ObjCId => String::from("ObjCId"),
+        TypeKind::ObjCClass => String::from("ObjCClass"),
+        TypeKind::ObjCSel => String::from("ObjCSel"),
+        TypeKind::Float128 => String::from("Float128"),
+        TypeKind::ObjCInterface => String::from("ObjCInterface"),
+        TypeKind::ObjCObjectPointer => String::from("ObjCObjectPointer"),
+        TypeKind::Pointer => String::from("Pointer"),
+        TypeKind::BlockPointer => String::from("BlockPointer"),
+        TypeKind::MemberPointer => String::from("MemberPointer"),
+        TypeKind::LValueReference => String::from("LValueReference"),
+        TypeKind::RValueReference => String::from("RValueReference"),
+        TypeKind::Enum => String::from("Enum"),
+        TypeKind::Record => String::from("Record"),
+        TypeKind::Typedef => String::from("Typedef"),
+        TypeKind::FunctionPrototype => String::from("FunctionPrototype"),
+        TypeKind::FunctionNoPrototype => String::from("FunctionNoPrototype"),
+        TypeKind::ConstantArray => String::from("ConstantArray"),
+        TypeKind::DependentSizedArray => String::from("DependentSizedArray"),
+        TypeKind::IncompleteArray => String::from("IncompleteArray"),
+        TypeKind::VariableArray => String::from("VariableArray"),
+        TypeKind::Vector => String::from("Vector"),
+        TypeKind::Auto => String::from("Auto"),
+        TypeKind::Elaborated => String::from("Elaborated"),
+        TypeKind::Pipe => String::from("Pipe"),
+        TypeKind::OCLImage1dRO => String::from("OCLImage1dRO"),
+        TypeKind::OCLImage1dArrayRO => String::from("OCLImage1dArrayRO"),
+        TypeKind::OCLImage1dBufferRO => String::from("OCLImage1dBufferRO"),
+        TypeKind::OCLImage2dRO => String::from("OCLImage2dRO"),
+        TypeKind::OCLImage2dArrayRO => String::from("OCLImage2dArrayRO"),
+        TypeKind::OCLImage2dDepthRO => String::from("OCLImage2dDepthRO"),
+        TypeKind::OCLImage2dArrayDepthRO => String::from("OCLImage2dArrayDepthRO"),
+        TypeKind::OCLImage2dMSAARO => String::from("OCLImage2dMSAARO"),
+        TypeKind::OCLImage2dArrayMSAARO => String::from("OCLImage2dArrayMSAARO"),
+        TypeKind::OCLImage2dMSAADepthRO => String::from("OCLImage2dMSAADepthRO"),
+        TypeKind::OCLImage2dArrayMSAADepthRO => String::from("OCLImage2dArrayMSAADepthRO"),
+        TypeKind::OCLImage3dRO => String::from("OCLImage3dRO"),
+        TypeKind::OCLImage1dWO => String::from("OCLImage1dWO"),
+        TypeKind::OCLImage1dArrayWO => String::from("OCLImage1dArrayWO"),
+        TypeKind::OCLImage1dBufferWO => String::from("OCLImage1dBufferWO"),
+        TypeKind::OCLImage2dWO => String::from("OCLImage2dWO"),
+        TypeKind::OCLImage2dArrayWO => String::from("OCLImage2dArrayWO"),
+        TypeKind::OCLImage2dDepthWO => String::from("OCLImage2dDepthWO"),
+        TypeKind::OCLImage2dArrayDepthWO => String::from("OCLImage2dArrayDepthWO"),
+        TypeKind::OCLImage2dMSAAWO => String::from("OCLImage2dMSAAWO"),
+        TypeKind::OCLImage2dArrayMSAAWO => String::from("OCLImage2dArrayMSAAWO"),
+        TypeKind::OCLImage2dMSAADepthWO => String::from("OCLImage2dMSAADepthWO"),
+        TypeKind::OCLImage2dArrayMSAADepthWO => String::from("OCLImage2dArrayMSAADepthWO"),
+        TypeKind::OCLImage3dWO => String::from("OCLImage3dWO"),
+        TypeKind::OCLImage1dRW => String::from("OCLImage1dRW"),
+        TypeKind::OCLImage1dArrayRW => String::from("OCLImage1dArrayRW"),
+        TypeKind::OCLImage1dBufferRW => String::from("OCLImage1dBufferRW"),
+        TypeKind::OCLImage2dRW => String::from("OCLImage2dRW"),
+        TypeKind::OCLImage2dArrayRW => String::from("OCLImage2dArrayRW"),
+        TypeKind::OCLImage2dDepthRW => String::from("OCLImage2dDepthRW"),
+        TypeKind::OCLImage2dArrayDepthRW => String::from("OCLImage2dArrayDepthRW"),
+        TypeKind::OCLImage2dMSAARW => String::from("OCLImage2dMSAARW"),
+        TypeKind::OCLImage2dArrayMSAARW => String::from("OCLImage2dArrayMSAARW"),
+        TypeKind::OCLImage2dMSAADepthRW => String::from("OCLImage2dMSAADepthRW"),
+        TypeKind::OCLImage2dArrayMSAADepthRW => String::from("OCLImage2dArrayMSAADepthRW"),
+        TypeKind::OCLImage3dRW => String::from("OCLImage3dRW"),
+        TypeKind::OCLSampler => String::from("OCLSampler"),
+        TypeKind::OCLEvent => String::from("OCLEvent"),
+        TypeKind::OCLQueue => String::from("OCLQueue"),
+        TypeKind::OCLReserveID => String::from("OCLReserveID"),
+        TypeKind::ObjCObject => String::from("ObjCObject"),
+        TypeKind::ObjCTypeParam => String::from("ObjCTypeParam"),
+        TypeKind::Attributed => String::from("Attributed"),
+        TypeKind::OCLIntelSubgroupAVCMcePayload => String::from("OCLIntelSubgroupAVCMcePayload"),
+        TypeKind::OCLIntelSubgroupAVCImePayload => String::from("OCLIntelSubgroupAVCImePayload"),
+        TypeKind::OCLIntelSubgroupAVCRefPayload => String::from("OCLIntelSubgroupAVCRefPayload"),
+        TypeKind::OCLIntelSubgroupAVCSicPayload => String::from("OCLIntelSubgroupAVCSicPayload"),
+        TypeKind::OCLIntelSubgroupAVCMceResult => String::from("OCLIntelSubgroupAVCMceResult"),
+        TypeKind::OCLIntelSubgroupAVCImeResult => String::from("OCLIntelSubgroupAVCImeResult"),
+        TypeKind::OCLIntelSubgroupAVCRefResult => String::from("OCLIntelSubgroupAVCRefResult"),
+        TypeKind::OCLIntelSubgroupAVCSicResult => String::from("OCLIntelSubgroupAVCSicResult"),
+        TypeKind::OCLIntelSubgroupAVCImeResultSingleRefStreamout => String::from("OCLIntelSubgroupAVCImeResultSingleRefStreamout"),
+        TypeKind::OCLIntelSubgroupAVCImeResultDualRefStreamout => String::from("OCLIntelSubgroupAVCImeResultDualRefStreamout"),
+        TypeKind::OCLIntelSubgroupAVCImeSingleRefStreamin => String::from("OCLIntelSubgroupAVCImeSingleRefStreamin"),
+        TypeKind::OCLIntelSubgroupAVCImeDualRefStreamin => String::from("OCLIntelSubgroupAVCImeDualRefStreamin"),
+        TypeKind::ExtVector => String::from("ExtVector"),
+    }
+}
+
+pub fn get_severity_label(severity: Severity) -> String {
+    match severity {
+        Severity::Ignored => String::from("ignored"),
+        Severity::Note => String::from("note"),
+        Severity::Warning => String::from("warning"),
+        Severity::Error => String::from("error"),
+        Severity::Fatal => String::from("fatal"),
+    }
+}
+
+pub fn get_accessibility_label(accessibility: Accessibility) -> String {
+    match accessibility {
+        Accessibility::Private => String::from("private"),
+        Accessibility::Protected => String::from("protected"),
+        Accessibility::Public => String::from("public"),
+    }
+}
@@ -0,0 +1,142 @@
+/* 
+   Copyright (c) 2022 ArSysOp.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+  
+       http:  www.apache.org/licenses/LICENSE-2.0
+  
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+  
+   SPDX-License-Identifier: Apache-2.0
+  
+   Contributors:
+     ArSysOp - initial API and implementation
+*/
+
+//! Parses C and C++ files with libclang into ASTs written as JSON, CBOR, MessagePack, flat tables or an SQLite database.
+//!
+//! `parse_trees` runs the whole pipeline of the command line tool over a set of inputs. 
+//! `parse_file` and `parse_buffer` parse a single translation unit into an owned `Tree` instead:
+//!
+//! ```no_run
+//! use loft_cpp_rust::{parse_file, write_tree, Encoding, ParseOptions};
+//!
+//! let options = ParseOptions { arguments: vec![String::from("-std=c++17")], ..ParseOptions::default() };
+//! let tree = parse_file("src/main.cpp", &options)?;
+//! for function in tree.find_by_kind("FunctionDecl") {
+//!     println!("{} at {}", function.node.get_name(), function.node.get_location());
+//! }
+//! write_tree(&tree, std::io::stdout().lock(), Encoding::Json)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! `read_tree_file` reads such a JSON file back into a `Tree`, so analyses can run on archived files without libclang.
+//!
+//! libclang can only be used by one caller at a time in a process, concurrent calls wait for each other.
+
+use clang::{Index, Unsaved};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use parse_cpp::{get_parser, get_tu, with_clang, DIAGNOSTICS, EXCLUDE};
+use diagnostics::collect_diagnostics;
+
+pub(crate) mod json;
+pub(crate) mod graph;
+pub(crate) mod parse_cpp;
+pub(crate) mod kind;
+pub(crate) mod get_name;
+pub(crate) mod index;
+pub(crate) mod compilation_database;
+pub(crate) mod error;
+pub(crate) mod diagnostics;
+pub(crate) mod output;
+pub(crate) mod call_graph;
+pub(crate) mod dot;
+pub(crate) mod hierarchy;
+pub(crate) mod include_graph;
+pub(crate) mod database;
+pub(crate) mod reader;
+/// Semantic changes between two revisions of the ASTs.
+pub mod diff;
+/// Exported declarations of a project and their source and binary compatibility between snapshots.
+pub mod api;
+
+pub use compilation_database::read_compilation_database;
+pub use diagnostics::{DiagnosticInfo, FixItInfo};
+pub use error::{Error, EXIT_BREAKING_CHANGES, EXIT_INVALID_CONFIG, EXIT_IO_FAILED, EXIT_PARSE_FAILED};
+pub use graph::{Exclusion, Link, Node, NodeWriter, Position, Range, RangeEnd, Tree, TreeBuilder, TreeIter, TypeInfo, WriteDocument, 
+    AST, FORMAT_VERSION};
+pub use json::{Bracket, DocumentSerializer, Encoding, Format, TableFormat};
+pub use output::{common_root, normalize_path, write_output_file, Compression};
+pub use parse_cpp::{parse_trees, DiagnosticsOutput, Input, Options, Summary};
+pub use reader::{read_tree, read_tree_file};
+
+/// How a single translation unit is parsed into a `Tree`.
+#[derive(Clone, Debug)]
+pub struct ParseOptions {
+    /// Compiler arguments, e.g. `-I` directories, `-D` definitions and `-std`.
+    pub arguments: Vec<String>,
+    pub exclusion: Exclusion,
+    /// Nodes deeper than this are left out, their parents are marked as truncated.
+    pub max_depth: Option<usize>,
+    /// Attach the clang diagnostics to the root node.
+    pub diagnostics: bool,
+}
+
+/// System headers and preprocessing entities are excluded and diagnostics kept, as in the command line tool.
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            arguments: Vec::new(),
+            exclusion: Exclusion { system_headers: true, preprocessing: true, ..Exclusion::default() },
+            max_depth: None,
+            diagnostics: true,
+        }
+    }
+}
+
+/// Parses the file into an owned tree.
+pub fn parse_file(path: &str, options: &ParseOptions) -> Result<Tree, Error> {
+    parse(path, None, options)
+}
+
+/// Parses the in-memory contents as if they were the file `name`, 
+/// which does not need to exist. Headers are still read from the file system.
+pub fn parse_buffer(name: &str, contents: &str, options: &ParseOptions) -> Result<Tree, Error> {
+    parse(name, Some(contents), options)
+}
+
+fn parse(path: &str, contents: Option<&str>, options: &ParseOptions) -> Result<Tree, Error> {
+    with_clang(|clang| {
+        let index = Index::new(&clang, EXCLUDE, DIAGNOSTICS);
+        let mut parser = get_parser(&index, PathBuf::from(path));
+        parser.arguments(&options.arguments);
+        parser.detailed_preprocessing_record(!options.exclusion.preprocessing);
+        if let Some(contents) = contents {
+            parser.unsaved(&[Unsaved::new(path, contents)]);
+        }
+        let tu = get_tu(&parser).map_err(|err| Error::Parse(String::from(path), err))?;
+        let mut ast = AST::new(tu.get_entity(), &options.exclusion, options.max_depth);
+        if options.diagnostics {
+            ast.set_diagnostics(collect_diagnostics(&tu));
+        }
+        let mut builder = TreeBuilder::default();
+        ast.write(&mut builder).map_err(|err| Error::Io(String::from(path), err))?;
+        // the translation unit has no location, so it is never excluded
+        Ok(builder.into_tree().expect("the root node is always written"))
+    })?
+}
+
+/// Serializes the tree into the writer and hands the writer back, flushed.
+pub fn write_tree<W: Write>(tree: &Tree, out: W, encoding: Encoding) -> io::Result<W> {
+    let mut json = DocumentSerializer::with_encoding(out, encoding);
+    tree.serialize(&mut json)?;
+    json.flush()?;
+    Ok(json.into_inner())
+}
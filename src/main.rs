@@ -19,16 +19,15 @@
      ArSysOp - initial API and implementation
 */
 
-use std::{env, fs, path::PathBuf};
+use std::{collections::HashSet, env, fs, io, path::{Path, PathBuf}, process, thread};
+use glob::Pattern;
+use ignore::WalkBuilder;
 use rustop::opts;
-use parse_cpp::parse_trees;
-
-pub mod json;
-pub mod graph;
-pub mod parse_cpp;
-pub mod kind;
-pub mod get_name;
-pub mod index;
+use loft_cpp_rust::{parse_trees, read_compilation_database, DiagnosticsOutput, Input, Options, Summary};
+use loft_cpp_rust::{Compression, Encoding, Error, Exclusion, Format, TableFormat, EXIT_BREAKING_CHANGES};
+use loft_cpp_rust::{common_root, normalize_path, write_output_file};
+use loft_cpp_rust::diff::diff_paths;
+use loft_cpp_rust::api::compare_api_paths;
 
 fn check_slash(path: &mut String) {
     if !path.ends_with('/') {
@@ -38,8 +37,8 @@ fn check_slash(path: &mut String) {
 }
 
 fn append_includes(mut parse_options: Vec<String>, mut include_dirs: Vec<String>) -> Vec<String> {
-    include_dirs.append(env::split_paths(&env::var_os("PATH").unwrap())
-    .map(|path| String::from(path.to_str().unwrap())).collect::<Vec<String>>().as_mut());
+    include_dirs.append(env::split_paths(&env::var_os("PATH").unwrap_or_default())
+    .map(|path| path.to_string_lossy().to_string()).collect::<Vec<String>>().as_mut());
     for mut path in include_dirs {
         check_slash(&mut path);
         parse_options.push(String::from(String::from("-I") + &path));
@@ -47,74 +46,372 @@ fn append_includes(mut parse_options: Vec<String>, mut include_dirs: Vec<String>
     parse_options
 }
 
-fn fill_excludes(exclude: &mut Vec<String>, include_paths: Vec<String>) {
-    if *exclude == vec![String::from("-")] {
-        exclude.pop();
-    } else {
-        if exclude.is_empty() {
-            for mut path in include_paths {
-                check_slash(&mut path);
-                path.pop();
-                match path.rfind("/") {
-                    Some(last_slash) => {
-                        path.drain((last_slash - 1)..);
-                    },
-                    None => {}
-                }
-                exclude.push(path);
-            }
-        }
+const DEFAULT_EXTENSIONS: [&str; 9] = ["c", "cc", "cpp", "cxx", "c++", "h", "hh", "hpp", "hxx"];
+
+struct InputFilter {
+    extensions: Vec<String>,
+    include_globs: Vec<Pattern>,
+    exclude_globs: Vec<Pattern>,
+    gitignore: bool,
+}
+
+impl InputFilter {
+    fn new(extensions: Vec<String>, include_globs: Vec<String>, exclude_globs: Vec<String>, gitignore: bool) -> Result<InputFilter, Error> {
+        let extensions = if extensions.is_empty() {
+            DEFAULT_EXTENSIONS.iter().map(|extension| String::from(*extension)).collect()
+        } else {
+            extensions.iter().map(|extension| extension.trim_start_matches('.').to_lowercase()).collect()
+        };
+        let compile = |globs: Vec<String>| globs.into_iter()
+            .map(|glob| Pattern::new(&glob).map_err(|err| Error::Pattern(glob.clone(), err)))
+            .collect::<Result<Vec<Pattern>, Error>>();
+        Ok(InputFilter { extensions, include_globs: compile(include_globs)?, exclude_globs: compile(exclude_globs)?, gitignore })
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        let extension = path.extension().map(|extension| extension.to_string_lossy().to_lowercase());
+        let path = path.to_string_lossy().replace('\\', "/");
+        extension.is_some_and(|extension| self.extensions.contains(&extension))
+        && (self.include_globs.is_empty() || self.include_globs.iter().any(|glob| glob.matches(&path)))
+        && !self.exclude_globs.iter().any(|glob| glob.matches(&path))
     }
 }
 
-fn find_code_in_dir(path: &PathBuf) -> Vec<String> {
+fn find_code_in_dir(path: &PathBuf, filter: &InputFilter) -> Result<Vec<String>, Error> {
     let mut result = Vec::new();
-    if path.is_dir() {
-        let read_path = fs::read_dir(path).unwrap();
-        for entry in read_path {
-            let entry = entry.unwrap();
-            if entry.file_name().to_str().unwrap().contains(".cpp") || entry.file_name().to_str().unwrap().contains(".c") {
-                result.push(String::from(entry.path().to_str().unwrap()));
-            }
+    // the walker reports symlink loops as errors instead of following them, 
+    // .gitignore files apply outside of git repositories too, e.g. in source archives
+    let walker = WalkBuilder::new(path)
+    .follow_links(true)
+    .standard_filters(false)
+    .require_git(false)
+    .parents(filter.gitignore)
+    .git_ignore(filter.gitignore)
+    .git_global(filter.gitignore)
+    .git_exclude(filter.gitignore)
+    .sort_by_file_name(|left, right| left.cmp(right))
+    .build();
+    for entry in walker {
+        match entry {
+            Ok(entry) => {
+                if entry.file_type().is_some_and(|file_type| file_type.is_file()) && filter.matches(entry.path()) {
+                    result.push(entry.path().to_string_lossy().to_string());
+                }
+            },
+            Err(err) => {
+                if let Some(io_error) = err.io_error() {
+                    return Err(Error::Io(path.to_string_lossy().to_string(), io::Error::new(io_error.kind(), err.to_string())));
+                }
+                // symlink loops and invalid .gitignore lines, the rest of the directory is still walked
+                eprintln!("warning: {}", err);
+            },
         }
     }
-    result
+    Ok(result)
 }
 
-fn expand_input(input_files_and_dirs: Vec<String>) -> Vec<String> {
+fn expand_input(input_files_and_dirs: Vec<String>, filter: &InputFilter) -> Result<Vec<String>, Error> {
     let mut result = Vec::new();
+    // the same file may be reachable through several symlinks
+    let mut seen = HashSet::new();
     for mut path in input_files_and_dirs {
-        if PathBuf::from(path.clone()).is_dir() {
+        let paths = if PathBuf::from(path.clone()).is_dir() {
             check_slash(&mut path);
-            result.append(&mut find_code_in_dir(&PathBuf::from(path)));
+            find_code_in_dir(&PathBuf::from(path), filter)?
         } else {
-            result.push(path);
+            vec![path]
+        };
+        for path in paths {
+            if seen.insert(fs::canonicalize(&path).unwrap_or_else(|_| PathBuf::from(&path))) {
+                result.push(path);
+            }
         }
     }
-    result
+    Ok(result)
+}
+
+fn make_inputs(input_files: Vec<String>, parse_options: Vec<String>) -> Vec<Input> {
+    input_files
+    .into_iter()
+    .map(|path| Input { path, parse_options: parse_options.clone(), working_dir: None })
+    .collect()
+}
+
+fn parse_diagnostics_output(value: Option<String>, format: Format) -> Result<DiagnosticsOutput, Error> {
+    match (value.as_deref(), format) {
+        // flat tables have no place for diagnostics, they go to the sidecar file by default
+        (None, Format::Table(_)) | (Some("sidecar"), _) => Ok(DiagnosticsOutput::Sidecar),
+        (None | Some("inline"), Format::Tree(_) | Format::Sqlite) => Ok(DiagnosticsOutput::Inline),
+        (Some("none"), _) => Ok(DiagnosticsOutput::None),
+        (Some(value), _) => Err(Error::InvalidOption(String::from("diagnostics"), String::from(value))),
+    }
 }
 
-fn config() -> (Vec<String>, Vec<String>, String, Vec<String>) {
+fn parse_format(value: String) -> Result<Format, Error> {
+    match value.as_str() {
+        "json" => Ok(Format::Tree(Encoding::Json)),
+        "cbor" => Ok(Format::Tree(Encoding::Cbor)),
+        "msgpack" => Ok(Format::Tree(Encoding::MessagePack)),
+        "jsonl" => Ok(Format::Table(TableFormat::JsonLines)),
+        "csv" => Ok(Format::Table(TableFormat::Csv)),
+        "sqlite" => Ok(Format::Sqlite),
+        _ => Err(Error::InvalidOption(String::from("format"), value)),
+    }
+}
+
+fn parse_compression(value: String, format: Format) -> Result<Compression, Error> {
+    match (value.as_str(), format) {
+        ("none", _) => Ok(Compression::None),
+        // the database is updated in place, it can't be a compressed stream
+        (_, Format::Sqlite) => Err(Error::InvalidOption(String::from("compression"), value)),
+        ("gzip", _) => Ok(Compression::Gzip),
+        ("zstd", _) => Ok(Compression::Zstd),
+        _ => Err(Error::InvalidOption(String::from("compression"), value)),
+    }
+}
+
+fn config() -> Result<(Vec<Input>, Options), Error> {
     let (mut args, _) = opts! {
         opt input_files:Vec<String> = Vec::new(), 
-        desc:"Input files. If you put directory here, program will parse all .cpp and .c files there.", multi:true;
+        desc:"Input files. If you put directory here, program will parse all C and C++ files in it and its subdirectories.", multi:true;
         opt output_dir:String=String::from("./"), desc:"Output directory, default is the current.";
         opt include:Vec<String> = Vec::new(), desc:"Include path.", multi:true;
         opt parse_options:Vec<String> = Vec::new(), desc:"Options passed to clang directly.", multi:true;
         opt exclude:Vec<String> = Vec::new(), desc:"Directories from which AST nodes shouldn't be traversed. 
-        By default, program will try to put included libraries there which may not work correctly. 
-        Set \"-\" to exclude nothing.", multi:true;
+        Entities of the parsed file itself are always kept.", multi:true;
+        opt compilation_database:Option<String>, desc:"Directory containing compile_commands.json. 
+        If set, input files, their options and working directories are taken from it.";
+        opt filter:Vec<String> = Vec::new(), desc:"Glob patterns selecting compilation database entries by path.", multi:true;
+        opt jobs:usize = 1, desc:"Number of worker threads. They take turns using libclang, so files are still parsed one at a time. 
+        Set 0 to use all available cores.";
+        opt keep_going:bool, desc:"Continue with the remaining files after a file fails to parse or to be written.";
+        opt diagnostics:Option<String>, desc:"Where clang diagnostics are written: 
+        \"inline\" into the AST file, \"sidecar\" into a separate .diagnostics.json file, or \"none\". 
+        By default, inline, or sidecar with flat tables which can't hold them.";
+        opt source_root:Option<String>, desc:"Outputs mirror the location of the inputs below this directory. 
+        By default, it is the deepest directory containing all inputs.";
+        opt extensions:Vec<String> = Vec::new(), desc:"Extensions of the files taken from input directories. 
+        By default, c, cc, cpp, cxx, c++, h, hh, hpp and hxx.", multi:true;
+        opt include_glob:Vec<String> = Vec::new(), desc:"Only files from input directories matching one of these glob patterns are parsed.", multi:true;
+        opt exclude_glob:Vec<String> = Vec::new(), desc:"Files from input directories matching one of these glob patterns are skipped.", multi:true;
+        opt gitignore:bool = true, desc:"Don't skip files from input directories ignored by .gitignore.";
+        opt symbol_index:bool, desc:"Write symbols.json with declarations, definitions and references of every symbol in the project.";
+        opt call_graph:bool, desc:"Write callgraph.json and callgraph.dot with the calls between all functions in the project.";
+        opt class_hierarchy:bool, desc:"Write hierarchy.json with the bases, derived classes and virtual methods of every class in the project.";
+        opt include_graph:bool, desc:"Write the headers included by every file, directly and transitively, 
+        next to its AST, and includegraph.json and includegraph.dot with the includes of the whole project.";
+        opt system_headers:bool, desc:"Keep AST nodes declared in system headers, which are skipped by default.";
+        opt main_file_only:bool, desc:"Skip AST nodes declared in any file other than the parsed one.";
+        opt preprocessing:bool, desc:"Keep the inclusion directives and the macro definitions and expansions in the AST files.";
+        opt format:String = String::from("json"), desc:"Layout of the AST files: \"json\", \"cbor\" or \"msgpack\" for a nested tree, 
+        \"jsonl\" or \"csv\" for flat .nodes and .edges tables, \"sqlite\" for a single ast.sqlite database.";
+        opt max_depth:Option<usize>, desc:"Nodes nested deeper than this are left out of the AST files, 
+        their parents are marked as truncated. The root is at depth 0.";
+        opt compression:String = String::from("none"), desc:"Compression of the AST files: \"gzip\", \"zstd\" or \"none\".";
+        opt api_surface:bool, desc:"Write api.json with the signatures and layouts of the exported functions, methods, classes, 
+        enums and typedefs of the project.";
+        opt public_headers:Vec<String> = Vec::new(), desc:"Directories of the public headers, only their declarations 
+        are written to api.json. By default, all exported declarations are.", multi:true;
     }.parse_or_exit();
-    fill_excludes(&mut args.exclude, 
-    append_includes(args.parse_options.clone(), args.include.clone()));
     check_slash(&mut args.output_dir);
-    (expand_input(args.input_files), 
-    append_includes(args.parse_options, args.include.clone()), 
-    args.output_dir, 
-    args.exclude)
+    let inputs = match args.compilation_database {
+        Some(database_dir) => {
+            let mut inputs = read_compilation_database(database_dir, args.filter)?;
+            for input in &mut inputs {
+                input.parse_options.extend(args.parse_options.clone());
+            }
+            inputs
+        },
+        None => {
+            let filter = InputFilter::new(args.extensions, args.include_glob, args.exclude_glob, args.gitignore)?;
+            make_inputs(expand_input(args.input_files, &filter)?, append_includes(args.parse_options, args.include.clone()))
+        },
+    };
+    let jobs = match args.jobs {
+        0 => thread::available_parallelism().map_or(1, |jobs| jobs.get()),
+        jobs => jobs,
+    };
+    let format = parse_format(args.format)?;
+    let source_root = args.source_root.unwrap_or_else(|| 
+        common_root(&inputs.iter().map(|input| input.path.clone()).collect::<Vec<String>>()));
+    Ok((inputs, 
+    Options { 
+        output_dir: args.output_dir, 
+        source_root,
+        exclusion: Exclusion {
+            system_headers: !args.system_headers,
+            other_files: args.main_file_only,
+            prefixes: args.exclude.iter().map(|path| normalize_path(path)).collect(),
+            preprocessing: !args.preprocessing,
+        },
+        max_depth: args.max_depth,
+        jobs, 
+        keep_going: args.keep_going, 
+        diagnostics: parse_diagnostics_output(args.diagnostics, format)?,
+        format,
+        compression: parse_compression(args.compression, format)?,
+        symbol_index: args.symbol_index,
+        call_graph: args.call_graph,
+        class_hierarchy: args.class_hierarchy,
+        include_graph: args.include_graph,
+        api_surface: args.api_surface,
+        public_headers: args.public_headers.iter().map(|path| normalize_path(path)).collect(),
+    }))
+}
+
+fn report(summary: &Summary) {
+    for err in &summary.failed {
+        eprintln!("error: {}", err);
+    }
+    eprintln!("{} files processed, {} failed, {} skipped", 
+    summary.succeeded.len() + summary.failed.len(), summary.failed.len(), summary.skipped.len());
+}
+
+// old and new path, and the file receiving the changes, of the commands comparing two existing outputs; 
+// the texts of the help page are literals since opts! declares its parser as an item
+macro_rules! parse_comparison_args {
+    ($args:expr, $command_name:literal, $synopsis:literal, $old:literal, $new:literal, $output:literal) => {{
+        let (args, _) = match opts! {
+            command_name $command_name;
+            synopsis $synopsis;
+            param old:String, desc:$old;
+            param new:String, desc:$new;
+            opt output:String = String::from($output), desc:"File receiving the changes as JSON.";
+        }.parse_args($args.iter().map(String::as_str)) {
+            Ok(parsed) => parsed,
+            Err(rustop::Error::Help(msg)) => {
+                eprintln!("{}", msg);
+                process::exit(1);
+            },
+            Err(err) => rustop::error_and_exit(&err),
+        };
+        (args.old, args.new, args.output)
+    }};
+}
+
+const DIFF_COMMAND: &str = "diff";
+
+fn diff(args: Vec<String>) -> Result<(), Error> {
+    let (old, new, output) = parse_comparison_args!(args, "loft-cpp-rust diff", 
+        "Reports the declarations added, removed, changed or moved between two AST files or two output directories.",
+        "JSON AST file or output directory of the old revision.", "JSON AST file or output directory of the new revision.", 
+        "diff.json");
+    let diff = diff_paths(&old, &new)?;
+    write_output_file(&diff, &output, "")?;
+    diff.write_summary(&mut io::stdout().lock()).map_err(|err| Error::Io(String::from("stdout"), err))
+}
+
+const API_DIFF_COMMAND: &str = "api-diff";
+
+// true if any change breaks source or binary compatibility
+fn api_diff(args: Vec<String>) -> Result<bool, Error> {
+    let (old, new, output) = parse_comparison_args!(args, "loft-cpp-rust api-diff", 
+        "Reports the changes between two API snapshots written with --api-surface, 
+        flagging the ones breaking source or binary compatibility.",
+        "api.json file or output directory of the old snapshot.", "api.json file or output directory of the new snapshot.", 
+        "apidiff.json");
+    let diff = compare_api_paths(&old, &new)?;
+    write_output_file(&diff, &output, "")?;
+    diff.write_summary(&mut io::stdout().lock()).map_err(|err| Error::Io(String::from("stdout"), err))?;
+    Ok(diff.is_breaking())
+}
+
+// prints the error and exits with its code
+fn exit_on_error<T>(result: Result<T, Error>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        process::exit(err.exit_code())
+    })
 }
 
 fn main() {
-    let (input_files, parse_options, output_dir, exclude_dirs) = config();
-    parse_trees(input_files, parse_options, output_dir, exclude_dirs);
+    // diff and api-diff compare existing outputs instead of parsing sources
+    match env::args().nth(1).as_deref() {
+        Some(DIFF_COMMAND) => return exit_on_error(diff(env::args().skip(2).collect())),
+        Some(API_DIFF_COMMAND) => match exit_on_error(api_diff(env::args().skip(2).collect())) {
+            true => process::exit(EXIT_BREAKING_CHANGES),
+            false => return,
+        },
+        _ => {},
+    }
+    let (inputs, options) = exit_on_error(config());
+    let summary = parse_trees(inputs, options);
+    report(&summary);
+    process::exit(summary.exit_code());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(extensions: &[&str], include_globs: &[&str], exclude_globs: &[&str]) -> InputFilter {
+        let strings = |values: &[&str]| values.iter().map(|value| String::from(*value)).collect();
+        InputFilter::new(strings(extensions), strings(include_globs), strings(exclude_globs), false).unwrap()
+    }
+
+    #[test]
+    fn default_extensions_are_matched_case_insensitively() {
+        let filter = filter(&[], &[], &[]);
+        for path in ["src/a.cpp", "src/a.C", "src/a.HPP", "src/a.c++"] {
+            assert!(filter.matches(Path::new(path)), "{}", path);
+        }
+        for path in ["src/a.txt", "src/Makefile", "src/a.cpp.orig"] {
+            assert!(!filter.matches(Path::new(path)), "{}", path);
+        }
+    }
+
+    #[test]
+    fn given_extensions_replace_the_default_ones() {
+        let filter = filter(&[".CU", "ipp"], &[], &[]);
+        assert!(filter.matches(Path::new("src/kernel.cu")));
+        assert!(filter.matches(Path::new("src/detail.ipp")));
+        assert!(!filter.matches(Path::new("src/a.cpp")));
+    }
+
+    #[test]
+    fn exclude_globs_win_over_include_globs() {
+        let filter = filter(&[], &["src/**"], &["**/test/**", "**/*_generated.h"]);
+        assert!(filter.matches(Path::new("src/lib/a.cpp")));
+        assert!(filter.matches(Path::new("src\\lib\\a.cpp")));
+        assert!(!filter.matches(Path::new("src/test/a.cpp")));
+        assert!(!filter.matches(Path::new("src/lib/a_generated.h")));
+        assert!(!filter.matches(Path::new("other/a.cpp")));
+    }
+
+    #[test]
+    fn gitignore_applies_outside_of_git_repositories() {
+        let dir = std::env::temp_dir().join(format!("loft-cpp-rust-walk-{}", std::process::id()));
+        fs::create_dir_all(dir.join("generated")).unwrap();
+        fs::write(dir.join(".gitignore"), "generated/\n").unwrap();
+        for file in ["a.cpp", "generated/b.cpp"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let found = |gitignore| {
+            let filter = InputFilter::new(Vec::new(), Vec::new(), Vec::new(), gitignore).unwrap();
+            find_code_in_dir(&dir, &filter).unwrap().iter()
+            .map(|path| Path::new(path).strip_prefix(&dir).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect::<Vec<String>>()
+        };
+        let (ignoring, all) = (found(true), found(false));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(ignoring, ["a.cpp"]);
+        assert_eq!(all, ["a.cpp", "generated/b.cpp"]);
+    }
+
+    #[test]
+    fn invalid_glob_is_reported() {
+        let globs = vec![String::from("src/[")];
+        assert!(matches!(InputFilter::new(Vec::new(), globs, Vec::new(), false), Err(Error::Pattern(glob, _)) if glob == "src/["));
+    }
+
+    #[test]
+    fn inline_diagnostics_are_rejected_with_flat_tables() {
+        let table = Format::Table(TableFormat::Csv);
+        assert_eq!(parse_diagnostics_output(None, table).unwrap(), DiagnosticsOutput::Sidecar);
+        assert!(matches!(parse_diagnostics_output(Some(String::from("inline")), table), 
+            Err(Error::InvalidOption(option, value)) if option == "diagnostics" && value == "inline"));
+        assert_eq!(parse_diagnostics_output(Some(String::from("none")), table).unwrap(), DiagnosticsOutput::None);
+        assert_eq!(parse_diagnostics_output(None, Format::Tree(Encoding::Json)).unwrap(), DiagnosticsOutput::Inline);
+        assert_eq!(parse_diagnostics_output(None, Format::Sqlite).unwrap(), DiagnosticsOutput::Inline);
+    }
 }
@@ -0,0 +1,320 @@
+/* 
+   Copyright (c) 2022 ArSysOp.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+  
+       http:  www.apache.org/licenses/LICENSE-2.0
+  
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+  
+   SPDX-License-Identifier: Apache-2.0
+  
+   Contributors:
+     ArSysOp - initial API and implementation
+*/
+
+use std::fs::{create_dir_all, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{self, Component, Path, PathBuf};
+use crate::error::Error;
+use crate::graph::{Array, Key, WriteDocument, TableNodeWriter, AST, EDGE_COLUMNS, NODE_COLUMNS, TYPE_COLUMNS};
+use flate2::write::GzEncoder;
+use crate::json::{Bracket, Encoding, Format, DocumentSerializer, TableFormat, TableSerializer};
+
+pub const JSON_EXTENSION: &str = ".json";
+pub const CBOR_EXTENSION: &str = ".cbor";
+pub const MESSAGE_PACK_EXTENSION: &str = ".msgpack";
+pub const NODES_EXTENSION: &str = ".nodes";
+pub const EDGES_EXTENSION: &str = ".edges";
+pub const TYPES_EXTENSION: &str = ".types";
+pub const DIAGNOSTICS_EXTENSION: &str = ".diagnostics.json";
+pub const INCLUDES_EXTENSION: &str = ".includes.json";
+pub const INDEX_FILE_NAME: &str = "index.json";
+pub const SYMBOLS_FILE_NAME: &str = "symbols.json";
+pub const CALL_GRAPH_FILE_NAME: &str = "callgraph.json";
+pub const CALL_GRAPH_DOT_FILE_NAME: &str = "callgraph.dot";
+pub const HIERARCHY_FILE_NAME: &str = "hierarchy.json";
+pub const DATABASE_FILE_NAME: &str = "ast.sqlite";
+pub const INCLUDE_GRAPH_FILE_NAME: &str = "includegraph.json";
+pub const INCLUDE_GRAPH_DOT_FILE_NAME: &str = "includegraph.dot";
+pub const API_FILE_NAME: &str = "api.json";
+
+/// Makes the path absolute and resolves `.` and `..` lexically, without touching the file system.
+pub fn normalize_path(path: &str) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path::absolute(path).unwrap_or_else(|_| PathBuf::from(path)).components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                result.pop();
+            },
+            component => result.push(component),
+        }
+    }
+    result
+}
+
+/// The deepest directory containing all the given files.
+pub fn common_root(paths: &[String]) -> String {
+    let mut root: Option<PathBuf> = None;
+    for path in paths {
+        let dir = normalize_path(path).parent().map(PathBuf::from).unwrap_or_default();
+        root = Some(match root {
+            None => dir,
+            Some(root) => root.components()
+                .zip(dir.components())
+                .take_while(|(left, right)| left == right)
+                .map(|(component, _)| component)
+                .collect(),
+        });
+    }
+    root.map_or(String::from("."), |root| root.to_string_lossy().to_string())
+}
+
+/// Path of the output file relative to the output directory. It mirrors the location of the 
+/// input below `source_root` and keeps the input extension, so `foo.c` and `foo.cpp` do not collide. 
+/// Inputs outside of `source_root` keep their whole absolute path below the output directory.
+pub fn get_relative_output_path(input_file_path: &str, source_root: &str, extension: &str) -> String {
+    let path = normalize_path(input_file_path);
+    let relative = match path.strip_prefix(normalize_path(source_root)) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => path.components()
+            .filter_map(|component| match component {
+                Component::Prefix(prefix) => Some(PathBuf::from(prefix.as_os_str().to_string_lossy().replace(':', ""))),
+                Component::Normal(part) => Some(PathBuf::from(part)),
+                _ => None,
+            })
+            .collect(),
+    };
+    relative.to_string_lossy().replace('\\', "/") + extension
+}
+
+pub fn create_output_file(relative_path: &str, output_dir: &str) -> Result<(File, String), Error> {
+    let output_file_path = Path::new(output_dir).join(relative_path).to_string_lossy().to_string();
+    if let Some(parent) = PathBuf::from(&output_file_path).parent() {
+        create_dir_all(parent).map_err(|err| Error::Io(parent.to_string_lossy().to_string(), err))?;
+    }
+    let file = File::create(&output_file_path).map_err(|err| Error::Io(output_file_path.clone(), err))?;
+    Ok((file, output_file_path))
+}
+
+/// Compression of the AST files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn get_extension(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+}
+
+/// Buffered output file, compressed on the fly. 
+/// `finish` writes the end of the compressed stream, dropping the writer instead ignores write errors.
+pub enum OutputWriter {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputWriter::Plain(out) => out.write(buf),
+            OutputWriter::Gzip(out) => out.write(buf),
+            OutputWriter::Zstd(out) => out.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputWriter::Plain(out) => out.flush(),
+            OutputWriter::Gzip(out) => out.flush(),
+            OutputWriter::Zstd(out) => out.flush(),
+        }
+    }
+}
+
+impl OutputWriter {
+    fn new(out: File, compression: Compression) -> io::Result<OutputWriter> {
+        let out = BufWriter::new(out);
+        Ok(match compression {
+            Compression::None => OutputWriter::Plain(out),
+            Compression::Gzip => OutputWriter::Gzip(GzEncoder::new(out, flate2::Compression::default())),
+            Compression::Zstd => OutputWriter::Zstd(zstd::Encoder::new(out, zstd::DEFAULT_COMPRESSION_LEVEL)?),
+        })
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            OutputWriter::Plain(mut out) => out.flush(),
+            OutputWriter::Gzip(out) => out.finish()?.flush(),
+            OutputWriter::Zstd(out) => out.finish()?.flush(),
+        }
+    }
+}
+
+fn create_compressed_output_file(relative_path: &str, output_dir: &str, compression: Compression) -> Result<(OutputWriter, String), Error> {
+    let (out, output_file_path) = create_output_file(relative_path, output_dir)?;
+    let out = OutputWriter::new(out, compression).map_err(|err| Error::Io(output_file_path.clone(), err))?;
+    Ok((out, output_file_path))
+}
+
+/// Writes the value as JSON into `relative_path` below `output_dir`, creating the missing directories.
+pub fn write_output_file<T: WriteDocument>(value: &T, relative_path: &str, output_dir: &str) -> Result<(), Error> {
+    write_encoded_file(value, relative_path, output_dir, Encoding::Json, Compression::None)
+}
+
+pub fn write_encoded_file<T: WriteDocument>(value: &T, relative_path: &str, output_dir: &str, encoding: Encoding, compression: Compression) 
+-> Result<(), Error> {
+    let (out, output_file_path) = create_compressed_output_file(relative_path, output_dir, compression)?;
+    let mut json = DocumentSerializer::with_encoding(out, encoding);
+    value.serialize(&mut json)
+    .and_then(|_| json.into_inner().finish())
+    .map_err(|err| Error::Io(output_file_path, err))
+}
+
+/// Path of the file receiving the AST of the input relative to the output directory, the node table for table formats.
+pub fn get_ast_output_path(input: &str, source_root: &str, format: Format, compression: Compression) -> String {
+    let extension = match format {
+        Format::Tree(Encoding::Json) => String::from(JSON_EXTENSION),
+        Format::Tree(Encoding::Cbor) => String::from(CBOR_EXTENSION),
+        Format::Tree(Encoding::MessagePack) => String::from(MESSAGE_PACK_EXTENSION),
+        Format::Table(table) => String::from(NODES_EXTENSION) + table.get_extension(),
+        Format::Sqlite => return String::from(DATABASE_FILE_NAME),
+    };
+    get_relative_output_path(input, source_root, &(extension + compression.get_extension()))
+}
+
+/// Writes the node, edge and type tables of the input next to each other.
+pub fn write_table_files(ast: &AST, input: &str, source_root: &str, format: TableFormat, compression: Compression, output_dir: &str) 
+-> Result<(), Error> {
+    let columns = |keys: &[Key]| keys.iter().map(Key::get_key).collect::<Vec<&'static [u8]>>();
+    let create = |extension: &str, keys: &[Key]| {
+        let relative_path = get_relative_output_path(input, source_root, 
+            &(String::from(extension) + format.get_extension() + compression.get_extension()));
+        let (out, output_file_path) = create_compressed_output_file(&relative_path, output_dir, compression)?;
+        TableSerializer::new(out, format, columns(keys))
+        .map(|table| (table, output_file_path.clone()))
+        .map_err(|err| Error::Io(output_file_path, err))
+    };
+    let (nodes, nodes_file_path) = create(NODES_EXTENSION, &NODE_COLUMNS)?;
+    let (edges, edges_file_path) = create(EDGES_EXTENSION, &EDGE_COLUMNS)?;
+    let (types, types_file_path) = create(TYPES_EXTENSION, &TYPE_COLUMNS)?;
+    let mut tables = TableNodeWriter { nodes, edges, types };
+    // a failed write can't be attributed to one table, report the node table
+    ast.write(&mut tables).map_err(|err| Error::Io(nodes_file_path.clone(), err))?;
+    tables.nodes.into_inner().finish().map_err(|err| Error::Io(nodes_file_path, err))?;
+    tables.edges.into_inner().finish().map_err(|err| Error::Io(edges_file_path, err))?;
+    tables.types.into_inner().finish().map_err(|err| Error::Io(types_file_path, err))
+}
+
+pub fn write_dot_file<F: FnOnce(&mut BufWriter<File>) -> io::Result<()>>(render: F, relative_path: &str, output_dir: &str) -> Result<(), Error> {
+    let (out, output_file_path) = create_output_file(relative_path, output_dir)?;
+    let mut out = BufWriter::new(out);
+    render(&mut out)
+    .and_then(|_| out.flush())
+    .map_err(|err| Error::Io(output_file_path, err))
+}
+
+#[derive(Clone, Debug)]
+struct IndexEntry {
+    input: (Key, String),
+    output: (Key, String),
+}
+
+impl WriteDocument for IndexEntry {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> io::Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        for attr in [&self.input, &self.output] {
+            json.render_line(attr.0.get_key(), attr.1.as_bytes())?;
+        }
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+/// Maps every successfully processed input to its output file, relative to the output directory.
+pub struct OutputIndex {
+    files: Array<IndexEntry>,
+}
+
+impl WriteDocument for OutputIndex {
+    fn serialize<W: Write>(&self, json: &mut DocumentSerializer<W>) -> io::Result<()> {
+        json.render_bracket(Bracket::LCurly)?;
+        json.prefix.expand();
+        self.files.serialize(json)?;
+        json.prefix.shrink();
+        json.render_bracket(Bracket::RCurly)
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct IndexFile {
+    files: Vec<IndexFileEntry>,
+}
+
+#[derive(serde::Deserialize)]
+struct IndexFileEntry {
+    input: String,
+    output: String,
+}
+
+/// Inputs of the `index.json` in the output directory with their output files, relative to the directory.
+pub fn read_output_index(output_dir: &str) -> Result<Vec<(String, String)>, Error> {
+    let path = PathBuf::from(output_dir).join(INDEX_FILE_NAME).to_string_lossy().to_string();
+    let file = File::open(&path).map_err(|err| Error::Io(path.clone(), err))?;
+    let index: IndexFile = serde_json::from_reader(io::BufReader::new(file)).map_err(|err| Error::Dump(path, err))?;
+    Ok(index.files.into_iter().map(|entry| (entry.input, entry.output)).collect())
+}
+
+impl OutputIndex {
+    pub fn new(files: Vec<(String, String)>) -> OutputIndex {
+        OutputIndex {
+            files: Array::new(Key::Files, files.into_iter()
+                .map(|(input, output)| IndexEntry { input: (Key::Input, input), output: (Key::Output, output) })
+                .collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn absolute(path: &str) -> String {
+        normalize_path(path).to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn common_root_is_the_deepest_shared_directory() {
+        assert_eq!(common_root(&[]), ".");
+        assert_eq!(common_root(&[String::from("src/a.cpp")]), absolute("src"));
+        assert_eq!(common_root(&[String::from("src/b/c.cpp"), String::from("src/b/d/e.cpp")]), absolute("src/b"));
+        assert_eq!(common_root(&[String::from("src/b/c.cpp"), String::from("src/bc/d.cpp")]), absolute("src"));
+        assert_eq!(common_root(&[String::from("src/b/../a.cpp"), String::from("./src/c.cpp")]), absolute("src"));
+    }
+
+    #[test]
+    fn output_path_mirrors_the_input_below_the_source_root() {
+        assert_eq!(get_relative_output_path("src/b/c.cpp", "src", ".json"), "b/c.cpp.json");
+        assert_eq!(get_relative_output_path("src/b/../c.c", "src/", ".json.gz"), "c.c.json.gz");
+        let outside = absolute("other/x.h");
+        let expected = outside.trim_start_matches('/').replace('\\', "/").replace(':', "") + ".json";
+        assert_eq!(get_relative_output_path("other/x.h", "src", ".json"), expected);
+    }
+}
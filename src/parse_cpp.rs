@@ -19,43 +19,320 @@
      ArSysOp - initial API and implementation
 */
 
-use crate::json::JSONSerializer;
 use clang::*;
-use std::{fs::*, io::Write, path::PathBuf};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError, atomic::{AtomicBool, AtomicUsize, Ordering}};
 use crate::graph::*;
+use crate::error::Error;
+use crate::database::Database;
+use crate::json::Format;
+use crate::diagnostics::{collect_diagnostics, DiagnosticsFile};
+use crate::output::*;
+use crate::index::{collect_entities, SymbolIndex};
+use crate::call_graph::{collect_calls, CallGraph};
+use crate::hierarchy::{collect_classes, ClassHierarchy};
+use crate::include_graph::{collect_includes, IncludeGraph, TranslationUnitIncludes};
+use crate::api::{collect_api, ApiSurface};
 
-const DIAGNOSTICS : bool = true;
-const EXCLUDE : bool = true;
+pub(crate) const DIAGNOSTICS : bool = true;
+pub(crate) const EXCLUDE : bool = true;
+// libclang parses recursively, give workers as much stack as the main thread has
+#[cfg(not(feature = "runtime"))]
+const WORKER_STACK_SIZE : usize = 8 * 1024 * 1024;
 
-fn get_parser<'a> (index: &'a Index, path: PathBuf) -> Parser<'a> {
+/// File to parse with its compiler arguments, and the directory they are relative to.
+#[derive(Clone, Debug)]
+pub struct Input {
+    pub path: String,
+    pub parse_options: Vec<String>,
+    pub working_dir: Option<String>,
+}
+
+/// Where the diagnostics of every translation unit are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiagnosticsOutput {
+    /// `diagnostics` array in the root node of the AST file, or the diagnostics table of the database. 
+    /// Flat tables can't hold them, they are then written as with `Sidecar`.
+    Inline,
+    /// Separate `<file name>.diagnostics.json` file next to the AST file.
+    Sidecar,
+    None,
+}
+
+/// What `parse_trees` writes and where.
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub output_dir: String,
+    /// Outputs mirror the location of their inputs below this directory.
+    pub source_root: String,
+    pub exclusion: Exclusion,
+    /// Nodes deeper than this are left out of the AST files, their parents are marked as truncated.
+    pub max_depth: Option<usize>,
+    pub jobs: usize,
+    pub keep_going: bool,
+    pub diagnostics: DiagnosticsOutput,
+    pub format: Format,
+    pub compression: Compression,
+    pub symbol_index: bool,
+    pub call_graph: bool,
+    pub class_hierarchy: bool,
+    pub include_graph: bool,
+    pub api_surface: bool,
+    /// Only declarations in files below these directories are part of the API surface, all of them if empty.
+    pub public_headers: Vec<PathBuf>,
+}
+
+/// Project-wide data collected from one translation unit, merged in input order once all workers finish.
+#[derive(Debug, Default)]
+struct Collected {
+    symbols: SymbolIndex,
+    calls: CallGraph,
+    classes: ClassHierarchy,
+    includes: IncludeGraph,
+    api: ApiSurface,
+}
+
+impl Collected {
+    fn merge(&mut self, other: Collected) {
+        self.symbols.merge(other.symbols);
+        self.calls.merge(other.calls);
+        self.classes.merge(other.classes);
+        self.includes.merge(other.includes);
+        self.api.merge(other.api);
+    }
+}
+
+/// Files that were parsed and written, files that failed, and files left untouched 
+/// after the first failure without `keep_going`, in input order.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub succeeded: Vec<String>,
+    pub failed: Vec<Error>,
+    pub skipped: Vec<String>,
+}
+
+impl Summary {
+    /// The most severe exit code among the failures, 0 if everything succeeded.
+    pub fn exit_code(&self) -> i32 {
+        self.failed.iter().map(Error::exit_code).max().unwrap_or(0)
+    }
+}
+
+// the mutex is held around every libclang call of the workers
+struct SharedClang(Clang, Mutex<()>);
+
+// SAFETY: the clang crate makes `Clang` neither `Send` nor `Sync` so that libclang is not used from several threads 
+// at once. Workers only call libclang with the mutex held, from creating their `Index` to dropping it, 
+// and nothing derived from an index leaves its worker, so libclang is never used by two threads at the same time. 
+// With the `runtime` feature of the clang crate, libclang is loaded for the creating thread only 
+// and `Clang` must stay on it, so workers are only spawned without it.
+#[cfg(not(feature = "runtime"))]
+unsafe impl Sync for SharedClang {}
+
+// libclang allows a single `Clang` per process, `parse_trees` and the library functions take turns
+static CLANG_LOCK: Mutex<()> = Mutex::new(());
+
+pub(crate) fn with_clang<T, F: FnOnce(Clang) -> T>(run: F) -> Result<T, Error> {
+    let _guard = CLANG_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    Clang::new().map(run).map_err(Error::Clang)
+}
+
+pub(crate) fn get_parser<'a> (index: &'a Index, path: PathBuf) -> Parser<'a> {
     index.parser(path)
 }
 
-fn get_tu<'a> (parser: &'a Parser) -> TranslationUnit<'a> {
-    parser.parse().expect("failed to parse file")
+pub(crate) fn get_tu<'a> (parser: &'a Parser) -> Result<TranslationUnit<'a>, SourceError> {
+    parser.parse()
 }
 
-fn create_output_file (input_file_path: String, output_dir: String) -> File {
-    create_dir_all(output_dir.clone()).unwrap();
-    let input_file_path_as_path = PathBuf::from(input_file_path);
-    let input_file_path_without_extension = PathBuf::from(input_file_path_as_path.file_stem().unwrap().to_str().unwrap());
-    let output_file_name = input_file_path_without_extension.file_name().unwrap().to_str().unwrap();
-    File::create(output_dir + output_file_name + ".json").unwrap()
+fn parse_tree (index: &Index, input: Input, position: usize, options: &Options, database: Option<&Database>) 
+-> Result<Collected, Error> {
+    let mut parse_options = input.parse_options;
+    if let Some(working_dir) = input.working_dir {
+        parse_options.push(format!("-working-directory={}", working_dir));
+    }
+    let mut parser = get_parser(index, PathBuf::from(input.path.clone()));
+    parser.arguments(&parse_options);
+    // inclusion directives are only reported with the detailed preprocessing record, 
+    // the AST keeps them and the macros only if they are not excluded
+    parser.detailed_preprocessing_record(options.include_graph || !options.exclusion.preprocessing);
+    let tu = get_tu(&parser).map_err(|err| Error::Parse(input.path.clone(), err))?;
+    let mut collected = Collected::default();
+    // the database always has a symbols table
+    if options.symbol_index || options.format == Format::Sqlite {
+        collected.symbols = collect_entities(tu.get_entity(), &input.path, &options.exclusion);
+    }
+    if options.call_graph {
+        collected.calls = collect_calls(tu.get_entity(), &options.exclusion);
+    }
+    if options.class_hierarchy {
+        collected.classes = collect_classes(tu.get_entity(), &options.exclusion);
+    }
+    if options.api_surface {
+        collected.api = collect_api(tu.get_entity(), &options.exclusion, &options.public_headers);
+    }
+    if options.include_graph {
+        collected.includes = collect_includes(&tu, &input.path);
+        write_output_file(&TranslationUnitIncludes::new(input.path.clone(), &collected.includes), 
+            &get_relative_output_path(&input.path, &options.source_root, INCLUDES_EXTENSION), &options.output_dir)?;
+    }
+    let mut ast = AST::new(tu.get_entity(), &options.exclusion, options.max_depth);
+    let mut diagnostics = collect_diagnostics(&tu);
+    match (options.diagnostics, options.format) {
+        (DiagnosticsOutput::Inline, Format::Tree(_)) => ast.set_diagnostics(std::mem::take(&mut diagnostics)),
+        (DiagnosticsOutput::Inline, Format::Sqlite) => {},
+        (DiagnosticsOutput::Sidecar, _) | (DiagnosticsOutput::Inline, Format::Table(_)) => write_output_file(&DiagnosticsFile::new(input.path.clone(), 
+            std::mem::take(&mut diagnostics)), 
+            &get_relative_output_path(&input.path, &options.source_root, DIAGNOSTICS_EXTENSION), &options.output_dir)?,
+        (DiagnosticsOutput::None, _) => diagnostics.clear(),
+    }
+    match options.format {
+        Format::Tree(encoding) => write_encoded_file(&ast, 
+            &get_ast_output_path(&input.path, &options.source_root, options.format, options.compression), &options.output_dir, 
+            encoding, options.compression)?,
+        Format::Table(format) => write_table_files(&ast, &input.path, &options.source_root, format, options.compression, 
+            &options.output_dir)?,
+        Format::Sqlite => if let Some(database) = database {
+            database.write_translation_unit(position, &ast, &diagnostics)?;
+        },
+    }
+    Ok(collected)
 }
 
-pub fn parse_trees (input_files: Vec<String>, mut parse_options: Vec<String>, output_dir: String, exclude_dirs: Vec<String>) {
-    let clang = Clang::new().unwrap();
-    let index = Index::new(&clang, EXCLUDE, DIAGNOSTICS);
-    for path in input_files {
-        let mut parser = get_parser(&index, PathBuf::from(path.clone()));
-        parser.arguments(&mut parse_options);
-        let tu = get_tu(&parser);
-        let out = create_output_file(path, output_dir.clone());
-        let mut json = JSONSerializer::new(out);
-        let mut ast = AST::new(tu.get_entity(), exclude_dirs.clone());
-        let node = Node::new(tu.get_entity(), &mut ast).0;
-        json = node.serialize(json);
-        json.writer.flush().unwrap();
+fn run_worker (clang: &SharedClang, inputs: &[Input], next: &AtomicUsize, stop: &AtomicBool, options: &Options, 
+    database: Option<&Database>) 
+-> Vec<(usize, Result<Collected, Error>)> {
+    let lock = || clang.1.lock().unwrap_or_else(PoisonError::into_inner);
+    // diagnostics go to the outputs, libclang printing them too would interleave the ones of all workers on stderr
+    let index = {
+        let _guard = lock();
+        Index::new(&clang.0, EXCLUDE, false)
+    };
+    let mut results = Vec::new();
+    while !stop.load(Ordering::SeqCst) {
+        let position = next.fetch_add(1, Ordering::SeqCst);
+        if position >= inputs.len() {
+            break;
+        }
+        let result = {
+            let _guard = lock();
+            parse_tree(&index, inputs[position].clone(), position, options, database)
+        };
+        if result.is_err() && !options.keep_going {
+            stop.store(true, Ordering::SeqCst);
+        }
+        results.push((position, result));
     }
+    let _guard = lock();
+    drop(index);
+    results
 }
 
+#[cfg(not(feature = "runtime"))]
+fn run_workers<R: Send, F: Fn(&SharedClang) -> Vec<R> + Sync>(clang: &SharedClang, jobs: usize, worker: F) -> Vec<R> {
+    std::thread::scope(|scope| {
+        let workers = (0..jobs)
+        .map(|_| std::thread::Builder::new()
+            .stack_size(WORKER_STACK_SIZE)
+            .spawn_scoped(scope, || worker(clang))
+            .expect("failed to spawn worker thread"))
+        .collect::<Vec<_>>();
+        workers
+        .into_iter()
+        .flat_map(|worker| worker.join().unwrap())
+        .collect::<Vec<_>>()
+    })
+}
+
+// libclang is only loaded for the thread that created `Clang`, the inputs are parsed on it one after the other
+#[cfg(feature = "runtime")]
+fn run_workers<R, F: Fn(&SharedClang) -> Vec<R>>(clang: &SharedClang, _jobs: usize, worker: F) -> Vec<R> {
+    worker(clang)
+}
+
+/// Parses the inputs and writes their ASTs and the requested project-wide data into the output directory.
+pub fn parse_trees (inputs: Vec<Input>, options: Options) -> Summary {
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let jobs = options.jobs.clamp(1, inputs.len().max(1));
+    let database = match options.format {
+        Format::Sqlite => match Database::create(&Path::new(&options.output_dir).join(DATABASE_FILE_NAME).to_string_lossy(), 
+            &inputs.iter().map(|input| input.path.clone()).collect::<Vec<String>>()) {
+            Ok(database) => Some(database),
+            Err(err) => return Summary {
+                failed: vec![err],
+                skipped: inputs.into_iter().map(|input| input.path).collect(),
+                ..Summary::default()
+            },
+        },
+        _ => None,
+    };
+    let results = with_clang(|clang| {
+        let clang = SharedClang(clang, Mutex::new(()));
+        run_workers(&clang, jobs, |clang| run_worker(clang, &inputs, &next, &stop, &options, database.as_ref()))
+    });
+    let mut results = match results {
+        Ok(results) => results,
+        Err(err) => return Summary {
+            failed: vec![err],
+            skipped: inputs.into_iter().map(|input| input.path).collect(),
+            ..Summary::default()
+        },
+    };
+    results.sort_by_key(|result| result.0);
+    let mut summary = Summary::default();
+    let mut project = Collected::default();
+    let mut results = results.into_iter().peekable();
+    for (position, input) in inputs.into_iter().enumerate() {
+        match results.next_if(|result| result.0 == position) {
+            Some((_, Ok(collected))) => {
+                project.merge(collected);
+                summary.succeeded.push(input.path);
+            },
+            Some((_, Err(err))) => summary.failed.push(err),
+            None => summary.skipped.push(input.path),
+        }
+    }
+    let index = OutputIndex::new(summary.succeeded
+        .iter()
+        .map(|path| (path.clone(), get_ast_output_path(path, &options.source_root, options.format, options.compression)))
+        .collect());
+    if let Err(err) = write_output_file(&index, INDEX_FILE_NAME, &options.output_dir) {
+        summary.failed.push(err);
+    }
+    if let Some(database) = database {
+        if let Err(err) = database.finish(&project.symbols) {
+            summary.failed.push(err);
+        }
+    }
+    if options.symbol_index {
+        if let Err(err) = write_output_file(&project.symbols, SYMBOLS_FILE_NAME, &options.output_dir) {
+            summary.failed.push(err);
+        }
+    }
+    if options.call_graph {
+        let written = write_output_file(&project.calls, CALL_GRAPH_FILE_NAME, &options.output_dir)
+        .and_then(|_| write_dot_file(|out| project.calls.write_dot(out), CALL_GRAPH_DOT_FILE_NAME, &options.output_dir));
+        if let Err(err) = written {
+            summary.failed.push(err);
+        }
+    }
+    if options.class_hierarchy {
+        if let Err(err) = write_output_file(&project.classes, HIERARCHY_FILE_NAME, &options.output_dir) {
+            summary.failed.push(err);
+        }
+    }
+    if options.api_surface {
+        if let Err(err) = write_output_file(&project.api, API_FILE_NAME, &options.output_dir) {
+            summary.failed.push(err);
+        }
+    }
+    if options.include_graph {
+        let written = write_output_file(&project.includes, INCLUDE_GRAPH_FILE_NAME, &options.output_dir)
+        .and_then(|_| write_dot_file(|out| project.includes.write_dot(out), INCLUDE_GRAPH_DOT_FILE_NAME, &options.output_dir));
+        if let Err(err) = written {
+            summary.failed.push(err);
+        }
+    }
+    summary
+}
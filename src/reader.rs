@@ -0,0 +1,247 @@
+/* 
+   Copyright (c) 2022 ArSysOp.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+  
+       http:  www.apache.org/licenses/LICENSE-2.0
+  
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+  
+   SPDX-License-Identifier: Apache-2.0
+  
+   Contributors:
+     ArSysOp - initial API and implementation
+*/
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use flate2::read::GzDecoder;
+use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
+use serde_json::{Map, Value};
+use crate::error::Error;
+use crate::graph::{Key, Node, Tree, FORMAT_VERSION};
+use crate::output::Compression;
+
+// reads the attributes of a node into a small map and its children one by one, 
+// so the subtrees are never buffered, only the root has to carry the supported format version
+fn visit_node<'de, A: MapAccess<'de>>(mut map: A, root: bool) -> Result<Tree, A::Error> {
+    let mut attributes = Map::new();
+    let mut children = Vec::new();
+    let mut version = None;
+    while let Some(key) = map.next_key::<String>()? {
+        if key.as_bytes() == Key::Children.get_key() {
+            children = map.next_value()?;
+        } else if key.as_bytes() == Key::FormatVersion.get_key() {
+            let found: u64 = map.next_value()?;
+            if root && found != FORMAT_VERSION {
+                return Err(de::Error::custom(format!("unsupported format version {}, expected {}", found, FORMAT_VERSION)));
+            }
+            version = Some(found);
+        } else {
+            attributes.insert(key, map.next_value()?);
+        }
+    }
+    if root && version.is_none() {
+        return Err(de::Error::custom(format!("missing format version, expected {}", FORMAT_VERSION)));
+    }
+    let node = Node::deserialize(Value::Object(attributes)).map_err(de::Error::custom)?;
+    Ok(Tree { node, children })
+}
+
+struct NodeVisitor {
+    root: bool,
+}
+
+impl<'de> Visitor<'de> for NodeVisitor {
+    type Value = Tree;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an AST node")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Tree, A::Error> {
+        visit_node(map, self.root)
+    }
+}
+
+impl<'de> Deserialize<'de> for Tree {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Tree, D::Error> {
+        deserializer.deserialize_map(NodeVisitor { root: false })
+    }
+}
+
+// root node of a tree file
+struct Dump(Tree);
+
+impl<'de> Deserialize<'de> for Dump {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Dump, D::Error> {
+        deserializer.deserialize_map(NodeVisitor { root: true }).map(Dump)
+    }
+}
+
+fn get_error(path: &str, err: serde_json::Error) -> Error {
+    if err.is_io() {
+        Error::Io(String::from(path), err.into())
+    } else {
+        Error::Dump(String::from(path), err)
+    }
+}
+
+/// Reads a tree written with `Encoding::Json` and checks its format version, `path` only names the input in errors. 
+/// The stack grows on demand, so the depth of the tree is only limited by memory.
+pub fn read_tree<R: Read>(input: R, path: &str) -> Result<Tree, Error> {
+    let mut json = serde_json::Deserializer::from_reader(input);
+    json.disable_recursion_limit();
+    Dump::deserialize(serde_stacker::Deserializer::new(&mut json))
+    .and_then(|dump| json.end().map(|_| dump.0))
+    .map_err(|err| get_error(path, err))
+}
+
+/// Reads the tree of a JSON AST file, `.gz` and `.zst` files are decompressed.
+pub fn read_tree_file(path: &str) -> Result<Tree, Error> {
+    let file = File::open(path).map_err(|err| Error::Io(String::from(path), err))?;
+    let input: Box<dyn Read> = if path.ends_with(Compression::Gzip.get_extension()) {
+        Box::new(GzDecoder::new(file))
+    } else if path.ends_with(Compression::Zstd.get_extension()) {
+        Box::new(zstd::Decoder::new(file).map_err(|err| Error::Io(String::from(path), err))?)
+    } else {
+        Box::new(file)
+    };
+    read_tree(BufReader::new(input), path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::tests::{deep_tree, node};
+    use crate::graph::{Link, Position, Range, RangeEnd, TypeInfo};
+    use crate::{write_tree, DiagnosticInfo, Encoding, FixItInfo};
+
+    fn sample_tree() -> Tree {
+        let mut tree = deep_tree(3);
+        let mut sibling = deep_tree(1);
+        sibling.node = node(3);
+        sibling.node.usr = String::from("c:@F@f#");
+        tree.children.push(sibling);
+        tree
+    }
+
+    fn type_info(display_name: &str, kind: &str) -> TypeInfo {
+        TypeInfo {
+            display_name: String::from(display_name),
+            canonical: String::from(display_name),
+            kind: String::from(kind),
+            pointee: None,
+            element: None,
+            result: None,
+        }
+    }
+
+    fn range() -> Range {
+        let end = |column| RangeEnd {
+            spelling: Position { file: String::from("a.h"), line: 2, column, offset: Some(17) },
+            expansion: Position { file: String::from("a.cpp"), line: 70_000, column, offset: Some(4_000_000_000) },
+            presumed: Position { file: String::from("a \"generated\".h"), line: 2, column, offset: None },
+        };
+        Range { begin: end(1), end: end(300) }
+    }
+
+    // a tree whose nodes have every optional attribute set
+    fn full_tree() -> Tree {
+        let mut tree = sample_tree();
+        let mut pointer = type_info("int (*)[4]", "Pointer");
+        let mut array = type_info("int[4]", "ConstantArray");
+        array.element = Some(Box::new(type_info("int", "Int")));
+        pointer.pointee = Some(Box::new(array));
+        let mut function = type_info("int *(char)", "FunctionProto");
+        function.result = Some(Box::new(pointer));
+        tree.node.diagnostics = Some(vec![DiagnosticInfo {
+            severity: String::from("error"),
+            location: String::from("a.cpp:3:5"),
+            message: String::from("use of undeclared identifier 'y'"),
+            category: String::from("Semantic Issue"),
+            option: String::new(),
+            ranges: vec![range()],
+            fix_its: vec![FixItInfo { kind: String::from("replacement"), range: range(), text: String::from("x") }],
+            notes: vec![DiagnosticInfo {
+                severity: String::from("note"),
+                location: String::from("a.h:1:5"),
+                message: String::from("'x' declared here"),
+                category: String::from("Semantic Issue"),
+                option: String::new(),
+                ranges: Vec::new(),
+                fix_its: Vec::new(),
+                notes: Vec::new(),
+            }],
+        }]);
+        let child = &mut tree.children[0].node;
+        child.kind = String::from("FunctionDecl");
+        child.name = String::from("f");
+        child.display_name = String::from("f(char)");
+        child.usr = String::from("c:@F@f#C#");
+        child.type_ = Some(function);
+        child.range = Some(range());
+        child.referenced = Some(Link::Id(1));
+        child.definition = Some(Link::Usr(String::from("c:@F@f#C#")));
+        child.canonical = Some(Link::Id(u64::MAX));
+        child.semantic_parent = Some(Link::Id(0));
+        child.lexical_parent = Some(Link::Usr(String::from("c:@N@n")));
+        child.truncated = true;
+        tree
+    }
+
+    #[test]
+    fn tree_with_every_attribute_is_read_back_in_every_encoding() {
+        let tree = full_tree();
+        let json = write_tree(&tree, Vec::new(), Encoding::Json).unwrap();
+        assert_eq!(read_tree(json.as_slice(), "a.cpp.json").unwrap(), tree);
+        let cbor = write_tree(&tree, Vec::new(), Encoding::Cbor).unwrap();
+        assert_eq!(ciborium::from_reader::<Tree, _>(cbor.as_slice()).unwrap(), tree);
+        let message_pack = write_tree(&tree, Vec::new(), Encoding::MessagePack).unwrap();
+        assert_eq!(rmp_serde::from_slice::<Tree>(&message_pack).unwrap(), tree);
+    }
+
+    #[test]
+    fn tree_written_by_the_json_writer_is_read_back() {
+        let tree = sample_tree();
+        let json = write_tree(&tree, Vec::new(), Encoding::Json).unwrap();
+        assert_eq!(read_tree(json.as_slice(), "a.cpp.json").unwrap(), tree);
+    }
+
+    #[test]
+    fn tree_serialized_with_serde_is_read_back() {
+        let tree = sample_tree();
+        let json = serde_json::to_vec(&tree).unwrap();
+        assert_eq!(read_tree(json.as_slice(), "a.cpp.json").unwrap(), tree);
+    }
+
+    #[test]
+    fn deep_tree_is_read_back() {
+        let tree = deep_tree(100_000);
+        let json = serde_json::to_vec(&tree).unwrap();
+        assert_eq!(read_tree(json.as_slice(), "a.cpp.json").unwrap(), tree);
+    }
+
+    #[test]
+    fn other_format_version_is_rejected() {
+        let json = serde_json::to_string(&sample_tree()).unwrap().replacen(
+            &format!(r#""formatVersion":{}"#, FORMAT_VERSION), &format!(r#""formatVersion":{}"#, FORMAT_VERSION + 1), 1);
+        let err = read_tree(json.as_bytes(), "a.cpp.json").unwrap_err();
+        assert!(matches!(err, Error::Dump(..)));
+        assert!(err.to_string().contains("unsupported format version"));
+    }
+
+    #[test]
+    fn missing_format_version_is_rejected() {
+        let json = serde_json::to_string(&sample_tree()).unwrap().replacen(&format!(r#""formatVersion":{},"#, FORMAT_VERSION), "", 1);
+        let err = read_tree(json.as_bytes(), "a.cpp.json").unwrap_err();
+        assert!(err.to_string().contains("missing format version"));
+    }
+}